@@ -0,0 +1,484 @@
+pub mod buffer;
+pub mod complete;
+pub mod expand;
+pub mod history;
+
+use crate::expr::State;
+use crate::expr::read::FdChars;
+use buffer::LineBuffer;
+use history::History;
+use nix::libc;
+use nix::sys::termios::{self, InputFlags, LocalFlags, SetArg, SpecialCharacterIndices, Termios};
+use nix::unistd::isatty;
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+// How long to wait for the rest of an escape sequence before taking the
+// escape key on its own.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Key {
+    Char(char),
+    Ctrl(char), // Ctrl-A is Ctrl('a')
+    Alt(char),  // or escape followed by the char
+    Up,
+    Down,
+    Left,
+    Right,
+    WordLeft, // the arrows with control held
+    WordRight,
+    Home,
+    End,
+    Delete,
+    Backspace,
+    Enter,
+    Tab,
+    Eof,
+    Unknown,
+}
+
+fn read_key(input: &mut FdChars) -> io::Result<Key> {
+    let Some(Some(c)) = input.next_char()? else {
+        return Ok(Key::Eof);
+    };
+    Ok(match c {
+        '\r' | '\n' => Key::Enter,
+        '\x7f' | '\x08' => Key::Backspace,
+        '\t' => Key::Tab,
+        '\x1b' => read_escape()?,
+        '\x01'..='\x1a' => Key::Ctrl((c as u8 - 1 + b'a') as char),
+        c if c.is_control() => Key::Unknown,
+        c => Key::Char(c),
+    })
+}
+
+// The rest of an escape sequence, ESC [ A for up and the like, or the
+// char typed with alt held.
+fn read_escape() -> io::Result<Key> {
+    let mut input = FdChars::new(libc::STDIN_FILENO, Some(ESCAPE_TIMEOUT));
+    let Some(Some(c)) = input.next_char()? else {
+        return Ok(Key::Unknown);
+    };
+    if c != '[' && c != 'O' {
+        return Ok(Key::Alt(c));
+    }
+    let mut params = String::new();
+    let last = loop {
+        match input.next_char()? {
+            Some(Some(c)) if c.is_ascii_digit() || c == ';' => params.push(c),
+            Some(Some(c)) => break c,
+            _ => return Ok(Key::Unknown),
+        }
+    };
+    let control = params.ends_with(";5");
+    Ok(match (last, params.as_str()) {
+        ('A', _) => Key::Up,
+        ('B', _) => Key::Down,
+        ('C', _) if control => Key::WordRight,
+        ('D', _) if control => Key::WordLeft,
+        ('C', _) => Key::Right,
+        ('D', _) => Key::Left,
+        ('H', _) | ('~', "1") | ('~', "7") => Key::Home,
+        ('F', _) | ('~', "4") | ('~', "8") => Key::End,
+        ('~', "3") => Key::Delete,
+        _ => Key::Unknown,
+    })
+}
+
+// The terminal without line buffering, echo or signal keys for as long as
+// this is around. Output processing stays on so \n still starts a line.
+struct RawMode {
+    original: Termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        let original = termios::tcgetattr(io::stdin())?;
+        let mut raw = original.clone();
+        raw.input_flags.remove(InputFlags::ICRNL | InputFlags::IXON);
+        raw.local_flags
+            .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN);
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        termios::tcsetattr(io::stdin(), SetArg::TCSADRAIN, &raw)?;
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(io::stdin(), SetArg::TCSADRAIN, &self.original);
+    }
+}
+
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if ok && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        80
+    }
+}
+
+// The row and column printing text from the start of a row ends up at.
+// Escape sequences, like colors in a prompt, take no room.
+fn position(text: &str, width: usize) -> (usize, usize) {
+    let (mut row, mut col) = (0, 0);
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => (row, col) = (row + 1, 0),
+            '\x1b' => {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            c if c.is_control() => {}
+            _ => {
+                col += 1;
+                if col == width {
+                    (row, col) = (row + 1, 0);
+                }
+            }
+        }
+    }
+    (row, col)
+}
+
+// What is on the screen, every change redraws the prompt and the whole
+// line, which may wrap or hold newlines, from the row the prompt is on.
+#[derive(Default)]
+struct Screen {
+    cursor_row: usize, // rows below the first prompt row the cursor is on
+}
+
+impl Screen {
+    fn refresh(&mut self, prompt: &str, line: &LineBuffer) -> io::Result<()> {
+        let width = terminal_width();
+        let text = line.text();
+        let before: String = text.chars().take(line.cursor()).collect();
+        let mut out = String::new();
+        if self.cursor_row > 0 {
+            out += &format!("\x1b[{}A", self.cursor_row);
+        }
+        let all = format!("{prompt}{text}");
+        out += "\r\x1b[J";
+        out += &all;
+        let (end_row, end_col) = position(&all, width);
+        // a full last row leaves the terminal waiting to wrap, move down
+        // for real so the rows counted are the rows on screen
+        if end_row > 0 && end_col == 0 && !all.ends_with('\n') {
+            out += "\n";
+        }
+        let (row, col) = position(&format!("{prompt}{before}"), width);
+        if end_row > row {
+            out += &format!("\x1b[{}A", end_row - row);
+        }
+        out += "\r";
+        if col > 0 {
+            out += &format!("\x1b[{col}C");
+        }
+        self.cursor_row = row;
+        let mut stdout = io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+
+    // redraws with the cursor at the end and moves past the line
+    fn finish(&mut self, prompt: &str, line: &mut LineBuffer, end: &str) -> io::Result<()> {
+        line.set_cursor(usize::MAX);
+        self.refresh(prompt, line)?;
+        let mut stdout = io::stdout();
+        writeln!(stdout, "{end}")?;
+        stdout.flush()
+    }
+}
+
+fn read_plain(prompt: &str) -> io::Result<Option<String>> {
+    let mut stdout = io::stdout();
+    write!(stdout, "{prompt}")?;
+    stdout.flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    Ok(Some(line.strip_suffix('\r').unwrap_or(line).to_string()))
+}
+
+// Lays items out in columns across the terminal, filling rows first.
+fn columns(items: &[&str], width: usize) -> String {
+    let column = items
+        .iter()
+        .map(|item| item.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let per_row = (width / column).max(1);
+    items
+        .chunks(per_row)
+        .map(|row| {
+            let row: Vec<String> = row.iter().map(|item| format!("{item:<column$}")).collect();
+            row.concat().trim_end().to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// the newest entry at or before from with query in it
+fn search_back(entries: &[String], query: &str, from: Option<usize>) -> Option<usize> {
+    entries[..=from?]
+        .iter()
+        .rposition(|entry| entry.contains(query))
+}
+
+// Reads lines at the interactive prompt with emacs style editing keys and
+// the history kept between sessions.
+pub struct Editor {
+    pub history: History,
+    killed: String, // what the last Ctrl-K, Ctrl-U or Ctrl-W took, for Ctrl-Y
+    state: Rc<RefCell<State>>, // what Tab completes from
+}
+
+impl Editor {
+    // Only a terminal gets its history saved, piped input is remembered
+    // for the session alone.
+    pub fn new(state: Rc<RefCell<State>>) -> Editor {
+        let history = if isatty(io::stdin()).unwrap_or(false) {
+            History::load()
+        } else {
+            History::load_from(None, history::histsize())
+        };
+        Editor {
+            history,
+            killed: String::new(),
+            state,
+        }
+    }
+
+    // None at the end of input, an Interrupted error when Ctrl-C threw the
+    // line away. When stdin isn't a terminal this is a plain read of the
+    // next line.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        if !isatty(io::stdin()).unwrap_or(false) {
+            return read_plain(prompt);
+        }
+        // they are shell variables and may have changed since the last line
+        self.history.file = history::histfile();
+        self.history.size = history::histsize();
+        let _raw = RawMode::enable()?;
+        self.edit(prompt)
+    }
+
+    fn edit(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut input = FdChars::new(libc::STDIN_FILENO, None);
+        let mut screen = Screen::default();
+        let mut line = LineBuffer::default();
+        // the history entry being shown, one past the end for the new line
+        let mut index = self.history.entries().len();
+        let mut unsaved = String::new(); // the new line while looking at history
+        let mut pending = None;
+        let mut last = Key::Unknown;
+        screen.refresh(prompt, &line)?;
+        loop {
+            let key = match pending.take() {
+                Some(key) => key,
+                None => read_key(&mut input)?,
+            };
+            let entries = self.history.entries();
+            match key {
+                Key::Enter => {
+                    screen.finish(prompt, &mut line, "")?;
+                    return Ok(Some(line.text()));
+                }
+                Key::Eof | Key::Ctrl('d') if line.is_empty() => {
+                    screen.finish(prompt, &mut line, "")?;
+                    return Ok(None);
+                }
+                Key::Eof => pending = Some(Key::Enter),
+                Key::Ctrl('c') => {
+                    screen.finish(prompt, &mut line, "^C")?;
+                    return Err(io::Error::from(io::ErrorKind::Interrupted));
+                }
+                Key::Char(c) => line.insert(&c.to_string()),
+                Key::Alt('\r') => line.insert("\n"),
+                Key::Home | Key::Ctrl('a') => line.home(),
+                Key::End | Key::Ctrl('e') => line.end(),
+                Key::Left | Key::Ctrl('b') => line.left(),
+                Key::Right | Key::Ctrl('f') => line.right(),
+                Key::WordLeft | Key::Alt('b') => line.word_left(),
+                Key::WordRight | Key::Alt('f') => line.word_right(),
+                Key::Backspace => line.backspace(),
+                Key::Delete | Key::Ctrl('d') => line.delete(),
+                Key::Ctrl('k') => self.killed = line.kill_to_end(),
+                Key::Ctrl('u') => self.killed = line.kill_to_start(),
+                Key::Ctrl('w') | Key::Alt('\x7f') => self.killed = line.kill_word_back(),
+                Key::Alt('d') => self.killed = line.kill_word_forward(),
+                Key::Ctrl('y') => line.insert(&self.killed),
+                Key::Ctrl('t') => line.transpose(),
+                Key::Ctrl('l') => {
+                    print!("\x1b[H\x1b[2J");
+                    screen.cursor_row = 0;
+                }
+                Key::Up | Key::Ctrl('p') if !line.on_first_line() => line.up(),
+                Key::Down | Key::Ctrl('n') if !line.on_last_line() => line.down(),
+                Key::Up | Key::Ctrl('p') if index > 0 => {
+                    if index == entries.len() {
+                        unsaved = line.text();
+                    }
+                    index -= 1;
+                    line.set(&entries[index]);
+                }
+                Key::Down | Key::Ctrl('n') if index < entries.len() => {
+                    index += 1;
+                    line.set(entries.get(index).unwrap_or(&unsaved));
+                }
+                Key::Ctrl('r') => {
+                    pending = self.search(&mut input, &mut screen, &mut line, &mut index)?;
+                }
+                Key::Tab => self.complete(prompt, &mut screen, &mut line, last == Key::Tab)?,
+                _ => {}
+            }
+            last = key;
+            screen.refresh(prompt, &line)?;
+        }
+    }
+
+    // Tab puts in the only match, or as much as all the matches share. When
+    // that adds nothing a second Tab lists them under the line.
+    fn complete(
+        &self,
+        prompt: &str,
+        screen: &mut Screen,
+        line: &mut LineBuffer,
+        again: bool,
+    ) -> io::Result<()> {
+        let before: String = line.text().chars().take(line.cursor()).collect();
+        let completion = complete::complete(&before, &self.state);
+        let word: String = before.chars().skip(completion.start).collect();
+        let mut stdout = io::stdout();
+        match completion.candidates.as_slice() {
+            [] => write!(stdout, "\x07")?,
+            [only] if only.ends_with('/') => line.replace_to_cursor(completion.start, only),
+            [only] => line.replace_to_cursor(completion.start, &format!("{only} ")),
+            candidates => {
+                let shared = complete::common_prefix(candidates);
+                if shared.len() > word.len() {
+                    line.replace_to_cursor(completion.start, &shared);
+                } else if again {
+                    let cursor = line.cursor();
+                    let shown: Vec<&str> = candidates
+                        .iter()
+                        .map(|candidate| &candidate[completion.hidden..])
+                        .collect();
+                    screen.finish(prompt, line, "")?;
+                    writeln!(stdout, "{}", columns(&shown, terminal_width()))?;
+                    screen.cursor_row = 0;
+                    line.set_cursor(cursor);
+                } else {
+                    write!(stdout, "\x07")?;
+                }
+            }
+        }
+        stdout.flush()
+    }
+
+    // Ctrl-R, looks back through history as the query is typed and Ctrl-R
+    // again finds the next older match. Any other key keeps the match and
+    // is handed back for edit to act on, None when Ctrl-G cancelled.
+    fn search(
+        &self,
+        input: &mut FdChars,
+        screen: &mut Screen,
+        line: &mut LineBuffer,
+        index: &mut usize,
+    ) -> io::Result<Option<Key>> {
+        let entries = self.history.entries();
+        let original = (line.text(), line.cursor(), *index);
+        let mut query = String::new();
+        let mut found = None;
+        let mut failed = false;
+        loop {
+            let failed_label = if failed { "failed " } else { "" };
+            screen.refresh(
+                &format!("({failed_label}reverse-i-search)`{query}': "),
+                line,
+            )?;
+            let from = match read_key(input)? {
+                Key::Char(c) => {
+                    query.push(c);
+                    found.or(entries.len().checked_sub(1))
+                }
+                Key::Ctrl('r') => match found {
+                    Some(found) => found.checked_sub(1),
+                    None => entries.len().checked_sub(1),
+                },
+                Key::Backspace => {
+                    query.pop();
+                    entries.len().checked_sub(1)
+                }
+                Key::Ctrl('g') | Key::Ctrl('c') => {
+                    line.set(&original.0);
+                    line.set_cursor(original.1);
+                    *index = original.2;
+                    return Ok(None);
+                }
+                key => return Ok(Some(key)),
+            };
+            if query.is_empty() {
+                failed = false;
+                continue;
+            }
+            match search_back(entries, &query, from) {
+                Some(i) => {
+                    let entry = &entries[i];
+                    let at = entry.rfind(&query).unwrap_or_default();
+                    line.set(entry);
+                    line.set_cursor(entry[..at].chars().count());
+                    (found, failed, *index) = (Some(i), false, i);
+                }
+                None => failed = true,
+            }
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::editor::{columns, position, search_back};
+
+    #[test]
+    fn test_position() {
+        assert_eq!(position("$ echo", 80), (0, 6));
+        assert_eq!(position("$ echo", 6), (1, 0));
+        assert_eq!(position("$ if true\n> then", 80), (1, 6));
+        assert_eq!(position("\x1b[1;32mslush\x1b[0m $ ", 80), (0, 8));
+        assert_eq!(position("abcdefg", 3), (2, 1));
+    }
+
+    #[test]
+    fn test_columns() {
+        let items = ["one", "two", "three", "four"];
+        assert_eq!(columns(&items, 16), "one    two\nthree  four");
+        assert_eq!(columns(&items, 80), "one    two    three  four");
+        assert_eq!(columns(&items, 3), "one\ntwo\nthree\nfour");
+    }
+
+    #[test]
+    fn test_search_back() {
+        let entries: Vec<String> = ["echo one", "ls", "echo two"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(search_back(&entries, "echo", Some(2)), Some(2));
+        assert_eq!(search_back(&entries, "echo", Some(1)), Some(0));
+        assert_eq!(search_back(&entries, "ls", Some(0)), None);
+        assert_eq!(search_back(&entries, "echo", None), None);
+    }
+}
@@ -0,0 +1,288 @@
+// The line being edited and the cursor in it, counted in chars. The text
+// can hold newlines, "lines" below are the pieces between them.
+#[derive(Debug, Default, PartialEq)]
+pub struct LineBuffer {
+    text: Vec<char>,
+    cursor: usize,
+}
+
+impl LineBuffer {
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    // replaces everything, the cursor goes to the end
+    pub fn set(&mut self, text: &str) {
+        self.text = text.chars().collect();
+        self.cursor = self.text.len();
+    }
+
+    pub fn set_cursor(&mut self, cursor: usize) {
+        self.cursor = cursor.min(self.text.len());
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        for c in text.chars() {
+            self.text.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    // swaps what is between start and the cursor for text
+    pub fn replace_to_cursor(&mut self, start: usize, text: &str) {
+        let start = start.min(self.cursor);
+        self.text.drain(start..self.cursor);
+        self.cursor = start;
+        self.insert(text);
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.text.remove(self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.text.len() {
+            self.text.remove(self.cursor);
+        }
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.text.len());
+    }
+
+    fn line_start(&self) -> usize {
+        self.text[..self.cursor]
+            .iter()
+            .rposition(|c| *c == '\n')
+            .map_or(0, |i| i + 1)
+    }
+
+    fn line_end(&self) -> usize {
+        self.text[self.cursor..]
+            .iter()
+            .position(|c| *c == '\n')
+            .map_or(self.text.len(), |i| self.cursor + i)
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = self.line_start();
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.line_end();
+    }
+
+    pub fn on_first_line(&self) -> bool {
+        !self.text[..self.cursor].contains(&'\n')
+    }
+
+    pub fn on_last_line(&self) -> bool {
+        !self.text[self.cursor..].contains(&'\n')
+    }
+
+    // to the same column of the line above, or its end if it is shorter
+    pub fn up(&mut self) {
+        let start = self.line_start();
+        if start == 0 {
+            return;
+        }
+        let column = self.cursor - start;
+        self.cursor = start - 1;
+        let above = self.line_start();
+        self.cursor = (above + column).min(start - 1);
+    }
+
+    pub fn down(&mut self) {
+        let column = self.cursor - self.line_start();
+        let end = self.line_end();
+        if end == self.text.len() {
+            return;
+        }
+        self.cursor = end + 1;
+        self.cursor = (end + 1 + column).min(self.line_end());
+    }
+
+    // the start of this word, or of the one before when already there
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !self.text[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && self.text[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.text.len() && !self.text[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.text.len() && self.text[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+
+    pub fn word_left(&mut self) {
+        self.cursor = self.word_start();
+    }
+
+    pub fn word_right(&mut self) {
+        self.cursor = self.word_end();
+    }
+
+    fn remove(&mut self, start: usize, end: usize) -> String {
+        self.cursor = start;
+        self.text.drain(start..end).collect()
+    }
+
+    // Ctrl-K, at the end of a line it takes the newline instead
+    pub fn kill_to_end(&mut self) -> String {
+        let end = match self.line_end() {
+            end if end == self.cursor && end < self.text.len() => end + 1,
+            end => end,
+        };
+        self.remove(self.cursor, end)
+    }
+
+    pub fn kill_to_start(&mut self) -> String {
+        self.remove(self.line_start(), self.cursor)
+    }
+
+    // Ctrl-W goes back to whitespace rather than to a word boundary
+    pub fn kill_word_back(&mut self) -> String {
+        let mut start = self.cursor;
+        while start > 0 && self.text[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.text[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.remove(start, self.cursor)
+    }
+
+    pub fn kill_word_forward(&mut self) -> String {
+        let (start, end) = (self.cursor, self.word_end());
+        self.remove(start, end)
+    }
+
+    // swaps the chars around the cursor, at the end the last two
+    pub fn transpose(&mut self) {
+        if self.text.len() < 2 || self.cursor == 0 {
+            return;
+        }
+        if self.cursor == self.text.len() {
+            self.cursor -= 1;
+        }
+        self.text.swap(self.cursor - 1, self.cursor);
+        self.cursor += 1;
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::editor::buffer::LineBuffer;
+
+    #[allow(dead_code)]
+    fn buffer(text: &str, cursor: usize) -> LineBuffer {
+        let mut buffer = LineBuffer::default();
+        buffer.set(text);
+        buffer.set_cursor(cursor);
+        buffer
+    }
+
+    #[test]
+    fn test_insert_and_delete() {
+        let mut line = buffer("ech", 3);
+        line.insert("o hi");
+        assert_eq!(line.text(), "echo hi");
+        line.home();
+        line.delete();
+        line.right();
+        line.backspace();
+        assert_eq!(line.text(), "ho hi");
+        assert_eq!(line.cursor(), 0);
+    }
+
+    #[test]
+    fn test_words() {
+        let mut line = buffer("echo some-thing else", 20);
+        line.word_left();
+        assert_eq!(line.cursor(), 16);
+        line.word_left();
+        assert_eq!(line.cursor(), 10);
+        line.home();
+        line.word_right();
+        assert_eq!(line.cursor(), 4);
+        assert_eq!(line.kill_word_forward(), " some");
+        line.end();
+        assert_eq!(line.kill_word_back(), "else");
+        assert_eq!(line.kill_word_back(), "echo-thing ");
+        assert!(line.is_empty());
+    }
+
+    #[test]
+    fn test_kill() {
+        let mut line = buffer("one two", 3);
+        assert_eq!(line.kill_to_end(), " two");
+        assert_eq!(line.kill_to_start(), "one");
+        assert!(line.is_empty());
+        let mut line = buffer("a\nb", 1);
+        assert_eq!(line.kill_to_end(), "\n");
+        assert_eq!(line.text(), "ab");
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut line = buffer("if true\nthen echo yes\nfi", 20);
+        assert!(!line.on_first_line() && !line.on_last_line());
+        line.home();
+        assert_eq!(line.cursor(), 8);
+        line.up();
+        line.end();
+        assert_eq!(line.cursor(), 7);
+        line.set_cursor(19);
+        line.down();
+        assert_eq!(line.cursor(), 24);
+        assert!(line.on_last_line());
+        line.up();
+        assert_eq!(line.cursor(), 10);
+    }
+
+    #[test]
+    fn test_replace_to_cursor() {
+        let mut line = buffer("cat sr | wc", 6);
+        line.replace_to_cursor(4, "src/");
+        assert_eq!(
+            (line.text(), line.cursor()),
+            ("cat src/ | wc".to_string(), 8)
+        );
+    }
+
+    #[test]
+    fn test_transpose() {
+        let mut line = buffer("ab", 2);
+        line.transpose();
+        assert_eq!(line.text(), "ba");
+        let mut line = buffer("abc", 1);
+        line.transpose();
+        assert_eq!((line.text(), line.cursor()), ("bac".to_string(), 2));
+    }
+}
@@ -0,0 +1,335 @@
+use crate::expr::lookup::is_executable;
+use crate::expr::{State, is_valid_name};
+use crate::parser::tokenizer::{ShTokenType, tokens};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+// What Tab can put in place of the word before the cursor.
+#[derive(Debug, Default, PartialEq)]
+pub struct Completion {
+    pub start: usize,            // where the word starts, in chars
+    pub candidates: Vec<String>, // sorted, each one replaces the whole word
+    pub hidden: usize,           // leading bytes not worth listing, a path's directory
+}
+
+// What the word at the cursor is going to be.
+#[derive(Debug, PartialEq)]
+enum Context {
+    Command,
+    Argument,
+    Redirect, // the file after < or >
+}
+
+// chars that end a word unless they are escaped
+fn ends_word(c: char) -> bool {
+    c.is_whitespace() || ";|&<>()`".contains(c)
+}
+
+fn word_start(before: &[char]) -> usize {
+    let mut start = before.len();
+    while start > 0 && !(ends_word(before[start - 1]) && (start < 2 || before[start - 2] != '\\')) {
+        start -= 1;
+    }
+    start
+}
+
+// Runs the tokens before the word through just enough of the grammar to
+// tell whether a command name comes next. Assignments in front of a
+// command leave it in command position. None inside an unclosed quote.
+fn context(before: &str) -> Option<Context> {
+    let mut context = Context::Command;
+    let mut in_word = false;
+    let mut assignment = false;
+    for token in tokens(before, false).ok()? {
+        match token.token_type {
+            ShTokenType::WhiteSpace => {
+                if in_word && !assignment {
+                    context = Context::Argument;
+                }
+                in_word = false;
+            }
+            ShTokenType::SemiColon
+            | ShTokenType::Pipe
+            | ShTokenType::AndIf
+            | ShTokenType::OrIf
+            | ShTokenType::Control
+            | ShTokenType::NewLine
+            | ShTokenType::LeftParen
+            | ShTokenType::RightParen => {
+                (context, in_word) = (Context::Command, false);
+            }
+            ShTokenType::RedirectOut
+            | ShTokenType::RedirectIn
+            | ShTokenType::AppendOut
+            | ShTokenType::DuplicateOut
+            | ShTokenType::DuplicateIn => {
+                (context, in_word) = (Context::Redirect, false);
+            }
+            // keywords only count where a command could go
+            ShTokenType::If
+            | ShTokenType::Then
+            | ShTokenType::Else
+            | ShTokenType::Elif
+            | ShTokenType::While
+            | ShTokenType::Until
+            | ShTokenType::Do
+            | ShTokenType::Time
+            | ShTokenType::Bang
+            | ShTokenType::LeftBrace
+                if context == Context::Command && !in_word =>
+            {
+                in_word = false;
+            }
+            ShTokenType::Equal if in_word && context == Context::Command => assignment = true,
+            _ => {
+                if !in_word {
+                    assignment = false;
+                    // a redirect takes one word, after it things go on as before
+                    if context == Context::Redirect {
+                        context = Context::Argument;
+                    }
+                }
+                in_word = true;
+            }
+        }
+    }
+    Some(context)
+}
+
+// Backslashes whatever the tokenizer would otherwise split or expand.
+fn escape(name: &str) -> String {
+    let mut escaped = String::new();
+    for c in name.chars() {
+        if c.is_whitespace() || "\\'\"`$&|;<>()*?[]{}#~!".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn unescape(word: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+pub fn complete(before: &str, state: &Rc<RefCell<State>>) -> Completion {
+    let chars: Vec<char> = before.chars().collect();
+    let start = word_start(&chars);
+    let word: String = chars[start..].iter().collect();
+    let prefix: String = chars[..start].iter().collect();
+    let Some(context) = context(&prefix) else {
+        return Completion::default();
+    };
+    let (candidates, hidden) = if let Some(dollar) = word.rfind('$') {
+        (variables(&word[..dollar], &word[dollar + 1..]), 0)
+    } else if word.starts_with('%') && context == Context::Argument {
+        (jobs(&word, &state.borrow()), 0)
+    } else if context == Context::Command && !word.contains('/') {
+        (commands(&word, &state.borrow()), 0)
+    } else {
+        files(&word, &state.borrow().cwd, context == Context::Command)
+    };
+    Completion {
+        start,
+        candidates,
+        hidden,
+    }
+}
+
+// $NAME or ${NAME} after whatever came before the dollar sign
+fn variables(lead: &str, partial: &str) -> Vec<String> {
+    let (brace, partial) = match partial.strip_prefix('{') {
+        Some(partial) => (true, partial),
+        None => (false, partial),
+    };
+    let names: BTreeSet<String> = env::vars_os()
+        .filter_map(|(name, _)| name.into_string().ok())
+        .filter(|name| is_valid_name(name) && name.starts_with(partial))
+        .collect();
+    names
+        .into_iter()
+        .map(|name| match brace {
+            true => format!("{lead}${{{name}}}"),
+            false => format!("{lead}${name}"),
+        })
+        .collect()
+}
+
+// %1, %2 and so on along with %name for the background jobs
+fn jobs(word: &str, state: &State) -> Vec<String> {
+    let specs: BTreeSet<String> = state
+        .bg_jobs
+        .iter()
+        .enumerate()
+        .flat_map(|(i, job)| [format!("%{}", i + 1), format!("%{}", job.cmd.name())])
+        .filter(|spec| spec.starts_with(word))
+        .collect();
+    specs.into_iter().collect()
+}
+
+fn commands(word: &str, state: &State) -> Vec<String> {
+    let partial = unescape(word);
+    let mut names: BTreeSet<String> = BTreeSet::new();
+    names.extend(state.built_ins.keys().cloned());
+    names.extend(state.functions.keys().cloned());
+    names.extend(state.aliases.keys().cloned());
+    let path = env::var("PATH").unwrap_or_default();
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if let Ok(name) = entry.file_name().into_string()
+                && name.starts_with(&partial)
+                && is_executable(&entry.path()) == Some(true)
+            {
+                names.insert(name);
+            }
+        }
+    }
+    names
+        .into_iter()
+        .filter(|name| name.starts_with(&partial))
+        .map(|name| escape(&name))
+        .collect()
+}
+
+// Paths are relative to the logical working directory, directories end in
+// a slash. Hidden files only come up when the name being typed starts with
+// a dot. As a command only directories and programs are any use.
+fn files(word: &str, cwd: &str, command: bool) -> (Vec<String>, usize) {
+    let word = unescape(word);
+    let (dir, partial) = match word.rfind('/') {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word.as_str()),
+    };
+    let Ok(entries) = fs::read_dir(Path::new(cwd).join(if dir.is_empty() { "." } else { dir }))
+    else {
+        return (Vec::new(), 0);
+    };
+    let mut names = BTreeSet::new();
+    for entry in entries.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if !name.starts_with(partial) || (name.starts_with('.') && !partial.starts_with('.')) {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            names.insert(format!("{}/", escape(&name)));
+        } else if !command || is_executable(&path) == Some(true) {
+            names.insert(escape(&name));
+        }
+    }
+    let dir = escape(dir);
+    let candidates = names
+        .into_iter()
+        .map(|name| format!("{dir}{name}"))
+        .collect();
+    (candidates, dir.len())
+}
+
+// the longest start every candidate shares
+pub fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first
+            .char_indices()
+            .zip(candidate.chars())
+            .take_while(|((i, a), b)| *i < len && a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8());
+    }
+    first[..len].to_string()
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::editor::complete::{Context, common_prefix, complete, context, escape, unescape};
+    #[allow(unused_imports)]
+    use crate::expr::State;
+    #[allow(unused_imports)]
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_context() {
+        assert_eq!(context(""), Some(Context::Command));
+        assert_eq!(context("echo "), Some(Context::Argument));
+        assert_eq!(context("ls | "), Some(Context::Command));
+        assert_eq!(context("A=1 B=2 "), Some(Context::Command));
+        assert_eq!(context("if "), Some(Context::Command));
+        assert_eq!(context("echo if "), Some(Context::Argument));
+        assert_eq!(context("cat < "), Some(Context::Redirect));
+        assert_eq!(context("cat <in "), Some(Context::Argument));
+        assert_eq!(context("true && "), Some(Context::Command));
+        assert_eq!(context("echo 'open "), None);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("my file (1).txt"), "my\\ file\\ \\(1\\).txt");
+        assert_eq!(unescape(&escape("a b$c")), "a b$c");
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        let words =
+            |words: &[&str]| -> Vec<String> { words.iter().map(|w| w.to_string()).collect() };
+        assert_eq!(common_prefix(&words(&["alias", "alpha"])), "al");
+        assert_eq!(common_prefix(&words(&["héllo", "hélium"])), "hél");
+        assert_eq!(common_prefix(&words(&["one"])), "one");
+        assert_eq!(common_prefix(&words(&[])), "");
+    }
+
+    #[test]
+    fn test_complete_commands_and_variables() {
+        let state = State::new();
+        state
+            .borrow()
+            .set_variable("SLUSH_COMPLETE_TEST", "1")
+            .unwrap();
+        let completion = complete("echo x | unal", &state);
+        assert_eq!(completion.start, 9);
+        assert_eq!(completion.candidates, vec!["unalias"]);
+        let completion = complete("echo ${SLUSH_COMPLETE_T", &state);
+        assert_eq!(completion.candidates, vec!["${SLUSH_COMPLETE_TEST}"]);
+        let completion = complete("echo a$SLUSH_COMPLETE_T", &state);
+        assert_eq!(completion.candidates, vec!["a$SLUSH_COMPLETE_TEST"]);
+    }
+
+    #[test]
+    fn test_complete_files() {
+        let dir = env::temp_dir().join(format!("slush_complete_test_{}", process::id()));
+        fs::create_dir_all(dir.join("sub dir")).unwrap();
+        fs::write(dir.join("sub dir/notes.txt"), "").unwrap();
+        fs::write(dir.join("sub dir/.hidden"), "").unwrap();
+        fs::write(dir.join("script"), "").unwrap();
+        let state = State::new();
+        state.borrow_mut().cwd = dir.to_string_lossy().to_string();
+        let completion = complete("cat su", &state);
+        assert_eq!(completion.candidates, vec!["sub\\ dir/"]);
+        let completion = complete("cat sub\\ dir/", &state);
+        assert_eq!(completion.candidates, vec!["sub\\ dir/notes.txt"]);
+        assert_eq!(completion.hidden, "sub\\ dir/".len());
+        // not executable, so no use as a command
+        assert!(complete("./scr", &state).candidates.is_empty());
+        assert_eq!(complete("echo hi > scr", &state).candidates, vec!["script"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,466 @@
+// csh style history expansion, done on each line typed at the prompt
+// before it is parsed:
+//   !!  the last command     !n  command n      !-n  n commands back
+//   !string  the last command starting with string
+//   !?string?  the last command containing string
+// then optionally a word of it, :0 :n :^ :$ :* :n-m :n* :n- (the colon can
+// go before ^ $ * and -), and modifiers, :h :t :r :e :s/old/new/ :gs/old/new/.
+// ^old^new at the start of a line is !!:s/old/new/.
+
+use std::mem;
+
+// Splits a command into words the way the designators count them, quotes
+// stay in the word they belong to and operators are words of their own.
+fn words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            (c, Some(q)) => {
+                word.push(c);
+                if c == q {
+                    quote = None;
+                } else if c == '\\' && q == '"' {
+                    word.extend(chars.next());
+                }
+            }
+            ('\\', None) => {
+                word.push(c);
+                word.extend(chars.next());
+            }
+            ('\'' | '"', None) => {
+                word.push(c);
+                quote = Some(c);
+            }
+            (c, None) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(mem::take(&mut word));
+                }
+            }
+            (c, None) if "|&;<>()".contains(c) => {
+                if !word.is_empty() {
+                    words.push(mem::take(&mut word));
+                }
+                let mut operator = c.to_string();
+                while let Some(next) = chars.next_if(|next| "|&;<>".contains(*next)) {
+                    operator.push(next);
+                }
+                words.push(operator);
+            }
+            (c, None) => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+// the command an event designator picks, and the chars it took after the '!'
+fn event<'a>(spec: &[char], history: &'a [String]) -> Result<(&'a str, usize), String> {
+    let not_found = |used: usize| -> String {
+        let text: String = spec[..used].iter().collect();
+        format!("!{text}: event not found")
+    };
+    let last = || history.last().map(|entry| entry.as_str());
+    let digits = |from: usize| {
+        spec[from..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count()
+    };
+    match spec.first() {
+        Some('!') => last().map(|entry| (entry, 1)).ok_or_else(|| not_found(1)),
+        // no event, so the word is from the last command
+        Some(':' | '^' | '$' | '*') => last().map(|entry| (entry, 0)).ok_or_else(|| not_found(1)),
+        Some('-') if digits(1) > 0 => {
+            let used = 1 + digits(1);
+            let back: usize = spec[1..used]
+                .iter()
+                .collect::<String>()
+                .parse()
+                .unwrap_or(0);
+            history
+                .len()
+                .checked_sub(back)
+                .and_then(|i| history.get(i))
+                .map(|entry| (entry.as_str(), used))
+                .ok_or_else(|| not_found(used))
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let used = digits(0);
+            let n: usize = spec[..used].iter().collect::<String>().parse().unwrap_or(0);
+            n.checked_sub(1)
+                .and_then(|i| history.get(i))
+                .map(|entry| (entry.as_str(), used))
+                .ok_or_else(|| not_found(used))
+        }
+        Some('?') => {
+            let text: String = spec[1..].iter().take_while(|c| **c != '?').collect();
+            let mut used = 1 + text.chars().count();
+            if spec.get(used) == Some(&'?') {
+                used += 1;
+            }
+            history
+                .iter()
+                .rev()
+                .find(|entry| entry.contains(&text))
+                .map(|entry| (entry.as_str(), used))
+                .ok_or_else(|| not_found(used))
+        }
+        _ => {
+            let prefix: String = spec
+                .iter()
+                .take_while(|c| !c.is_whitespace() && !":;&|<>()'\"".contains(**c))
+                .collect();
+            let used = prefix.chars().count();
+            // a bare '!' before ; or ) would otherwise match every command
+            if used == 0 {
+                return Err(not_found(0));
+            }
+            history
+                .iter()
+                .rev()
+                .find(|entry| entry.starts_with(&prefix))
+                .map(|entry| (entry.as_str(), used))
+                .ok_or_else(|| not_found(used))
+        }
+    }
+}
+
+// A number, ^ for 1 or $ for the last word. None when there isn't one.
+fn word_number(spec: &[char], last: usize) -> Option<(usize, usize)> {
+    match spec.first()? {
+        '^' => Some((1, 1)),
+        '$' => Some((last, 1)),
+        c if c.is_ascii_digit() => {
+            let used = spec.iter().take_while(|c| c.is_ascii_digit()).count();
+            let n = spec[..used].iter().collect::<String>().parse().ok()?;
+            Some((n, used))
+        }
+        _ => None,
+    }
+}
+
+// The words a word designator picks out of command, joined by spaces,
+// and the chars the designator took. A range past the end is an error.
+fn select(spec: &[char], command: &str) -> Result<(String, usize), String> {
+    let words = words(command);
+    let last = words.len().saturating_sub(1);
+    let (first, mut used) = match spec.first() {
+        Some('*') => {
+            let picked = words.get(1..).unwrap_or_default().join(" ");
+            return Ok((picked, 1));
+        }
+        Some('-') => (0, 0),
+        _ => word_number(spec, last).ok_or("bad word specifier")?,
+    };
+    let end = match spec.get(used) {
+        Some('*') => {
+            used += 1;
+            Some(last)
+        }
+        Some('-') => {
+            used += 1;
+            match word_number(&spec[used..], last) {
+                Some((end, n)) => {
+                    used += n;
+                    Some(end)
+                }
+                // n- stops short of the last word
+                None => last.checked_sub(1),
+            }
+        }
+        _ => Some(first),
+    };
+    match end {
+        // first one past end picks nothing, like 1- with only two words
+        Some(end) if end < words.len() && first <= end + 1 => {
+            Ok((words[first..=end].join(" "), used))
+        }
+        None if first == 0 => Ok((String::new(), used)),
+        _ => Err("bad word specifier".to_string()),
+    }
+}
+
+// :s/old/new/ with any char in place of the slashes, & in new stands
+// for old. The last delimiter can be left off at the end of the line.
+fn substitute(spec: &[char], text: &str, global: bool) -> Result<(String, usize), String> {
+    let Some(&delimiter) = spec.first() else {
+        return Err("bad substitution".to_string());
+    };
+    let mut used = 1;
+    let part = |used: &mut usize| -> String {
+        let mut part = String::new();
+        while let Some(&c) = spec.get(*used) {
+            *used += 1;
+            match c {
+                c if c == delimiter => break,
+                '\\' if spec.get(*used) == Some(&delimiter) => {
+                    part.push(delimiter);
+                    *used += 1;
+                }
+                c => part.push(c),
+            }
+        }
+        part
+    };
+    let old = part(&mut used);
+    let new = part(&mut used).replace('&', &old);
+    if old.is_empty() || !text.contains(&old) {
+        return Err(format!(
+            ":s{delimiter}{old}{delimiter}: substitution failed"
+        ));
+    }
+    let replaced = match global {
+        true => text.replace(&old, &new),
+        false => text.replacen(&old, &new, 1),
+    };
+    Ok((replaced, used))
+}
+
+// Applies the :h :t :r :e :s modifiers at the start of spec in turn. A
+// colon followed by anything else is left for the line itself.
+fn modify(spec: &[char], mut text: String) -> Result<(String, usize), String> {
+    let mut used = 0;
+    while spec.get(used) == Some(&':') {
+        match spec.get(used + 1) {
+            Some('h') => {
+                if let Some(slash) = text.rfind('/') {
+                    text.truncate(slash.max(1));
+                }
+            }
+            Some('t') => {
+                if let Some(slash) = text.rfind('/') {
+                    text.drain(..=slash);
+                }
+            }
+            Some('r') => {
+                let base = text.rfind('/').map_or(0, |slash| slash + 1);
+                if let Some(dot) = text[base..].rfind('.') {
+                    text.truncate(base + dot);
+                }
+            }
+            Some('e') => {
+                let base = text.rfind('/').map_or(0, |slash| slash + 1);
+                if let Some(dot) = text[base..].rfind('.') {
+                    text.drain(..base + dot);
+                }
+            }
+            Some('s') => {
+                let (replaced, n) = substitute(&spec[used + 2..], &text, false)?;
+                text = replaced;
+                used += n;
+            }
+            Some('g') if spec.get(used + 2) == Some(&'s') => {
+                let (replaced, n) = substitute(&spec[used + 3..], &text, true)?;
+                text = replaced;
+                used += n + 1;
+            }
+            _ => break,
+        }
+        used += 2;
+    }
+    Ok((text, used))
+}
+
+// One !designator at the start of spec, what it expands to and how many
+// chars it covered, the '!' included.
+fn designator(spec: &[char], history: &[String]) -> Result<(String, usize), String> {
+    let (command, mut used) = event(&spec[1..], history)?;
+    used += 1;
+    let rest = &spec[used..];
+    let word_start = match rest {
+        [':', c, ..] if c.is_ascii_digit() || "^$*-".contains(*c) => Some(1),
+        [c, ..] if "^$*-".contains(*c) => Some(0),
+        _ => None,
+    };
+    let text = match word_start {
+        Some(start) => {
+            let (text, n) = select(&rest[start..], command)?;
+            used += start + n;
+            text
+        }
+        None => command.to_string(),
+    };
+    let (text, n) = modify(&spec[used..], text)?;
+    Ok((text, used + n))
+}
+
+// Whether text ends inside single or double quotes, so a line that carries
+// on a command knows what it starts in.
+fn open_quotes(text: &str) -> (bool, bool) {
+    let (mut single, mut double) = (false, false);
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if !single => {
+                chars.next();
+            }
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            _ => {}
+        }
+    }
+    (single, double)
+}
+
+// The line with its history references replaced, None when there were
+// none. before is what was typed of the command on earlier lines. Nothing
+// inside single quotes or after a backslash is expanded, and a '!' before a
+// blank, '=' or '(' is just a '!', as is the one in $! and ${!name}.
+pub fn expand(line: &str, before: &str, history: &[String]) -> Result<Option<String>, String> {
+    let chars: Vec<char> = line.chars().collect();
+    if before.is_empty() && chars.first() == Some(&'^') {
+        let previous = history.last().ok_or("^: event not found")?;
+        let (text, used) = substitute(&chars, previous, false)?;
+        let rest: String = chars[used..].iter().collect();
+        return Ok(Some(text + &rest));
+    }
+    let mut expanded = String::new();
+    let mut changed = false;
+    let (mut single, mut double) = open_quotes(before);
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if !single => {
+                expanded.extend(&chars[i..(i + 2).min(chars.len())]);
+                i += 2;
+                continue;
+            }
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '!' if !single
+                && !matches!(chars[..i], [.., '$'] | [.., '$', '{'])
+                && chars
+                    .get(i + 1)
+                    .is_some_and(|next| !next.is_whitespace() && !"=(\"".contains(*next)) =>
+            {
+                let (text, used) = designator(&chars[i..], history)?;
+                expanded += &text;
+                changed = true;
+                i += used;
+                continue;
+            }
+            _ => {}
+        }
+        expanded.push(c);
+        i += 1;
+    }
+    Ok(changed.then_some(expanded))
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::editor::expand::{expand, open_quotes, words};
+
+    #[allow(dead_code)]
+    fn history() -> Vec<String> {
+        [
+            "ls /tmp/a.tar.gz",
+            "echo a | wc -l; x=\"q r\" 's t'",
+            "cat notes.txt",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+    }
+
+    #[allow(dead_code)]
+    fn expanded(line: &str) -> String {
+        match expand(line, "", &history()) {
+            Ok(Some(line)) => line,
+            Ok(None) => "unchanged".to_string(),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn test_words() {
+        assert_eq!(
+            words("echo a | wc -l; x=\"q r\" 's t'"),
+            ["echo", "a", "|", "wc", "-l", ";", "x=\"q r\"", "'s t'"]
+        );
+        assert_eq!(words("a\\ b 2>&1"), ["a\\ b", "2", ">&", "1"]);
+    }
+
+    #[test]
+    fn test_events() {
+        assert_eq!(expanded("sudo !!"), "sudo cat notes.txt");
+        assert_eq!(expanded("!1"), "ls /tmp/a.tar.gz");
+        assert_eq!(expanded("!-2"), "echo a | wc -l; x=\"q r\" 's t'");
+        assert_eq!(expanded("!ls && !?wc?:0"), "ls /tmp/a.tar.gz && echo");
+        assert_eq!(expanded("!nope"), "!nope: event not found");
+        assert_eq!(expanded("!9"), "!9: event not found");
+        assert_eq!(
+            expand("!!", "", &[]),
+            Err("!!: event not found".to_string())
+        );
+        assert_eq!(expanded("echo a !;"), "!: event not found");
+    }
+
+    #[test]
+    fn test_words_designators() {
+        assert_eq!(expanded("vi !$"), "vi notes.txt");
+        assert_eq!(expanded("!!:0 !^"), "cat notes.txt");
+        assert_eq!(expanded("!-2:3-4"), "wc -l");
+        assert_eq!(expanded("!-2:$"), "'s t'");
+        assert_eq!(expanded("!-2:6*"), "x=\"q r\" 's t'");
+        assert_eq!(expanded("!-2:5-"), "; x=\"q r\"");
+        assert_eq!(expanded("x!1:*x"), "x/tmp/a.tar.gzx");
+        assert_eq!(expanded("!1:1-"), "");
+        assert_eq!(expanded("!1:3"), "bad word specifier");
+    }
+
+    #[test]
+    fn test_modifiers() {
+        assert_eq!(expanded("!1:$:h"), "/tmp");
+        assert_eq!(expanded("!1:$:t"), "a.tar.gz");
+        assert_eq!(expanded("!1:$:r"), "/tmp/a.tar");
+        assert_eq!(expanded("!1:$:e"), ".gz");
+        assert_eq!(expanded("!1:$:h:t"), "tmp");
+        assert_eq!(expanded("!!:s/notes/&-old/"), "cat notes-old.txt");
+        assert_eq!(expanded("!-2:gs/ /_"), "echo_a_|_wc_-l;_x=\"q_r\"_'s_t'");
+        assert_eq!(expanded("!!:s/nope/x/"), ":s/nope/: substitution failed");
+        // not a modifier, the colon stays
+        assert_eq!(expanded("echo !!:q"), "echo cat notes.txt:q");
+    }
+
+    #[test]
+    fn test_quick_substitution() {
+        assert_eq!(expanded("^cat^less"), "less notes.txt");
+        assert_eq!(expanded("^notes^todo^ | wc"), "cat todo.txt | wc");
+    }
+
+    #[test]
+    fn test_left_alone() {
+        assert_eq!(expanded("echo hi"), "unchanged");
+        assert_eq!(expanded("echo '!!' \\!! ! != !("), "unchanged");
+        assert_eq!(expanded("echo \"!$\""), "echo \"notes.txt\"");
+        assert_eq!(expanded("echo done!"), "unchanged");
+        assert_eq!(expanded("echo $!; echo ${!x}"), "unchanged");
+        assert_eq!(expanded("echo $!!"), "unchanged");
+    }
+
+    #[test]
+    fn test_continued_lines() {
+        assert_eq!(open_quotes("echo 'a\\"), (true, false));
+        assert_eq!(open_quotes("echo \"it's\\\" "), (false, true));
+        assert_eq!(open_quotes("echo \\' 'b'"), (false, false));
+        let history = ["ls".to_string()];
+        // still inside the quote opened on the line before
+        assert_eq!(expand("!! x'", "echo 'a", &history), Ok(None));
+        assert_eq!(
+            expand("!! x\"", "echo \"a", &history),
+            Ok(Some("ls x\"".to_string()))
+        );
+        assert_eq!(
+            expand("'!!", "echo 'a", &history),
+            Ok(Some("'ls".to_string()))
+        );
+        assert_eq!(expand("^a^b", "echo \\", &history), Ok(None));
+    }
+}
@@ -0,0 +1,150 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+const DEFAULT_SIZE: usize = 500;
+
+// Lines entered at the prompt, oldest first. Every line is appended to the
+// history file as soon as it is added so a shell that gets killed keeps
+// what it had.
+#[derive(Debug)]
+pub struct History {
+    entries: Vec<String>,
+    pub file: Option<PathBuf>,
+    pub size: usize,
+}
+
+// $HISTFILE, ~/.slush_history when it isn't set. Setting it to nothing
+// turns saving off.
+pub fn histfile() -> Option<PathBuf> {
+    match env::var("HISTFILE") {
+        Ok(file) if file.is_empty() => None,
+        Ok(file) => Some(PathBuf::from(file)),
+        Err(_) => env::var("HOME")
+            .ok()
+            .map(|home| PathBuf::from(home).join(".slush_history")),
+    }
+}
+
+// $HISTSIZE, how many lines to keep
+pub fn histsize() -> usize {
+    env::var("HISTSIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_SIZE)
+}
+
+// One entry a line in the file, so the newlines of multi-line commands
+// and the backslashes that would be confused with them are escaped.
+fn encode(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn decode(line: &str) -> String {
+    let mut entry = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => entry.push('\n'),
+            ('\\', Some('\\')) => entry.push('\\'),
+            _ => {
+                entry.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    entry
+}
+
+impl History {
+    pub fn load() -> History {
+        History::load_from(histfile(), histsize())
+    }
+
+    pub fn load_from(file: Option<PathBuf>, size: usize) -> History {
+        let entries = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|text| text.lines().map(decode).collect())
+            .unwrap_or_default();
+        let mut history = History {
+            entries,
+            file,
+            size,
+        };
+        if history.trim() {
+            history.rewrite();
+        }
+        history
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // Blank lines and repeats of the line before are left out.
+    pub fn add(&mut self, line: &str) {
+        if line.trim().is_empty() || self.entries.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+        if self.trim() {
+            self.rewrite();
+        } else if let Some(file) = &self.file
+            && let Ok(mut file) = OpenOptions::new().create(true).append(true).open(file)
+        {
+            let _ = writeln!(file, "{}", encode(line));
+        }
+    }
+
+    // drops the oldest entries past size, true if there were any
+    fn trim(&mut self) -> bool {
+        let extra = self.entries.len().saturating_sub(self.size);
+        self.entries.drain(..extra);
+        extra > 0
+    }
+
+    fn rewrite(&self) {
+        if let Some(file) = &self.file {
+            let text: String = self
+                .entries
+                .iter()
+                .map(|entry| encode(entry) + "\n")
+                .collect();
+            let _ = fs::write(file, text);
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::editor::history::{History, decode, encode};
+    #[allow(unused_imports)]
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_encoding() {
+        let entry = "for i in 1 2\ndo echo \\n $i\ndone";
+        assert_eq!(encode(entry), "for i in 1 2\\ndo echo \\\\n $i\\ndone");
+        assert_eq!(decode(&encode(entry)), entry);
+        assert_eq!(decode("trailing \\"), "trailing \\");
+    }
+
+    #[test]
+    fn test_history_file() {
+        let file = env::temp_dir().join(format!("slush_history_test_{}", process::id()));
+        let mut history = History::load_from(Some(file.clone()), 3);
+        for line in ["one", "one", " ", "two\nlines", "three"] {
+            history.add(line);
+        }
+        assert_eq!(history.entries(), ["one", "two\nlines", "three"]);
+        let history = History::load_from(Some(file.clone()), 2);
+        assert_eq!(history.entries(), ["two\nlines", "three"]);
+        let mut history = History::load_from(Some(file.clone()), 2);
+        history.add("four");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "three\nfour\n");
+        fs::remove_file(file).unwrap();
+    }
+}
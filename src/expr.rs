@@ -1,19 +1,38 @@
 pub mod change_dir;
+pub mod getopts;
+pub mod lookup;
+pub mod options;
+pub mod pattern;
+pub mod prompt;
+pub mod read;
+pub mod redirect;
+pub mod resources;
+pub mod special;
+pub mod trap;
+pub mod umask;
 use crate::parser::Parser;
 use nix::libc;
+use nix::sys::resource::{UsageWho, getrusage};
+use nix::sys::signal::{self, SigHandler, Signal};
+use nix::sys::stat;
+use nix::sys::wait::{WaitStatus, waitpid};
+use nix::unistd::{ForkResult, Pid, fork, getppid};
 use shared_child::SharedChild;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
 use std::fmt;
 use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
 use std::io::{PipeReader, PipeWriter, Read, Write, pipe};
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process;
-use std::process::Stdio;
 use std::process::{Command, ExitStatus};
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Instant;
 use std::{env, io};
 
 pub type FunctionStack = Rc<RefCell<Vec<Rc<Vec<Argument>>>>>; // ??
@@ -25,6 +44,24 @@ pub struct State {
     pub built_ins: HashMap<String, BuiltIn>,
     pub functions: HashMap<String, Rc<RefCell<Vec<PipeLineExpr>>>>,
     pub argstack: FunctionStack,
+    pub shell_name: String,
+    pub getopts: getopts::GetoptsState,
+    pub readonly: HashSet<String>,
+    // one scope per function call, holding the values locals shadowed
+    pub local_scopes: Vec<HashMap<String, Option<String>>>,
+    pub aliases: HashMap<String, String>,
+    pub path_cache: lookup::PathCache,
+    pub cwd: String, // the logical working directory, what $PWD should be
+    // set when ${name?word} finds name unset, see check_expansion
+    pub expansion_failed: bool,
+    // set when a $(...) stores its status, see SubShellExpr::stdout
+    pub substituted: bool,
+    pub exit_trap: Option<String>, // what `trap ... EXIT` runs, see trap::exit
+    pub options: options::Options, // what `set -o` turned on
+    pub lineno: usize,             // line of the pipeline running now
+    pub ppid: Pid,                 // the shell's parent, subshells keep it
+    pub random: special::Random,
+    pub seconds: special::Seconds,
 }
 
 macro_rules! slushwrite {
@@ -44,6 +81,21 @@ impl State {
             fg_jobs: Vec::new(),
             prev_status: 0,
             argstack: FunctionStack::new(RefCell::new(Vec::new())),
+            shell_name: String::from("slush"),
+            getopts: getopts::GetoptsState::default(),
+            readonly: HashSet::new(),
+            local_scopes: Vec::new(),
+            aliases: HashMap::new(),
+            path_cache: lookup::PathCache::default(),
+            cwd: change_dir::initial_cwd(),
+            expansion_failed: false,
+            substituted: false,
+            exit_trap: None,
+            options: options::Options::default(),
+            lineno: 0,
+            ppid: getppid(),
+            random: special::Random::default(),
+            seconds: special::Seconds::default(),
             functions: HashMap::new(),
             built_ins: HashMap::from([
                 (
@@ -54,9 +106,65 @@ impl State {
                             |args: &Vec<Argument>,
                              state: Rc<RefCell<State>>,
                              _: &mut Option<Box<dyn Read>>,
-                             _: &mut Option<Box<dyn Write>>|
+                             out: &mut Option<Box<dyn Write>>|
                              -> i32 {
-                                change_dir::ChangeDir::new(&args[0].eval(&state)).eval()
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                let result = change_dir::ChangeDir::new(&args)
+                                    .and_then(|cd| cd.eval(&state));
+                                match result {
+                                    Ok(Some(dir)) => {
+                                        if let Some(writer) = out {
+                                            slushwrite!(writer, "{}", dir);
+                                        }
+                                        0
+                                    }
+                                    Ok(None) => 0,
+                                    Err(e) => {
+                                        eprintln!("slush: {e}");
+                                        1
+                                    }
+                                }
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "pwd".to_string(),
+                    BuiltIn {
+                        name: "pwd".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let mut physical = false;
+                                for arg in args {
+                                    match arg.eval(&state).as_str() {
+                                        "-L" => physical = false,
+                                        "-P" => physical = true,
+                                        opt => {
+                                            eprintln!("slush: pwd: {opt}: invalid option");
+                                            return 2;
+                                        }
+                                    }
+                                }
+                                let dir = if physical {
+                                    match env::current_dir() {
+                                        Ok(dir) => dir.to_string_lossy().into_owned(),
+                                        Err(e) => {
+                                            eprintln!("slush: pwd: {e}");
+                                            return 1;
+                                        }
+                                    }
+                                } else {
+                                    state.borrow().cwd.clone()
+                                };
+                                if let Some(writer) = out {
+                                    slushwrite!(writer, "{}", dir);
+                                }
+                                0
                             },
                         ),
                     },
@@ -144,7 +252,22 @@ impl State {
                                 if let Some(writer) = out {
                                     slushwrite!(writer, "slush: A shell you can drink!");
                                     slushwrite!(writer, "\nBuiltins:");
-                                    slushwrite!(writer, "  cd <dir> - change directory");
+                                    slushwrite!(
+                                        writer,
+                                        "  alias [name[=value]...] - define or list aliases"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  cd [-L|-P] [dir|-] - change directory, $HOME by default"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  pwd [-L|-P] - print the current directory"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  exec [command [args...]] - replace the shell with command, or apply redirects to the shell"
+                                    );
                                     slushwrite!(
                                         writer,
                                         "  exit [code] - exit the shell, optionally with a code"
@@ -153,9 +276,66 @@ impl State {
                                         writer,
                                         "  astview '<command>' - view the abstract syntax tree of a command"
                                     );
+                                    slushwrite!(
+                                        writer,
+                                        "  set [-+o option]... [--] [args...] - set options or replace the positional parameters"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  shift [n] - drop the first n positional parameters"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  trap [action EXIT] - run action when the shell exits, - resets it"
+                                    );
                                     slushwrite!(writer, "  true - return 0");
+                                    slushwrite!(
+                                        writer,
+                                        "  umask [-S] [mode] - show or set the file creation mask"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  ulimit [-HSa] [-cdfnstv] [limit] - show or set resource limits"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  times - print cpu time used by the shell and its children"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  command [-pvV] name [args...] - run or describe a command, skipping functions"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  type name... - describe how a name would be run"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  hash [-r] [name...] - remember or forget where commands are"
+                                    );
+                                    slushwrite!(writer, "  unalias [-a] name... - remove aliases");
                                     slushwrite!(writer, "  false - return 1");
+                                    slushwrite!(
+                                        writer,
+                                        "  getopts optstring name [args...] - parse options from the positional parameters"
+                                    );
                                     slushwrite!(writer, "  help - print this message");
+                                    slushwrite!(
+                                        writer,
+                                        "  local name[=value]... - make variables local to the current function"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  read [-r] [-p prompt] [-t secs] [-d delim] [name...] - read a line into variables"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  readonly [-p] [name[=value]...] - stop variables from being changed"
+                                    );
+                                    slushwrite!(
+                                        writer,
+                                        "  unset [-v|-f] name... - remove variables or functions"
+                                    );
                                 };
                                 0
                             },
@@ -169,27 +349,748 @@ impl State {
                         command: Rc::new(
                             |args: &Vec<Argument>,
                              state: Rc<RefCell<State>>,
-                             stdin: &mut Option<Box<dyn Read>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             _: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                // read works on fd 0 itself so it never takes more input
+                                // than it needs, the pipeline points fd 0 at our input.
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                let args = match read::ReadArgs::parse(&args) {
+                                    Ok(args) => args,
+                                    Err(e) => {
+                                        eprintln!("slush: {e}");
+                                        return 2;
+                                    }
+                                };
+                                if let Some(prompt) = &args.prompt
+                                    && unsafe { libc::isatty(0) } == 1
+                                {
+                                    eprint!("{prompt}");
+                                }
+                                let (line, end) =
+                                    match read::read_line(0, args.delim, args.raw, args.timeout) {
+                                        Ok(read) => read,
+                                        Err(e) => {
+                                            eprintln!("slush: read: {e}");
+                                            return 1;
+                                        }
+                                    };
+                                let values = if args.names.is_empty() {
+                                    vec![line.iter().map(|(c, _)| *c).collect()]
+                                } else {
+                                    let ifs = env::var("IFS").unwrap_or(" \t\n".to_string());
+                                    read::split_fields(&line, &ifs, args.names.len())
+                                };
+                                let names = if args.names.is_empty() {
+                                    vec!["REPLY".to_string()]
+                                } else {
+                                    args.names
+                                };
+                                for (name, value) in names.iter().zip(values) {
+                                    if let Err(e) = state.borrow().set_variable(name, &value) {
+                                        eprintln!("slush: read: {e}");
+                                        return 2;
+                                    }
+                                }
+                                match end {
+                                    read::LineEnd::Delim => 0,
+                                    read::LineEnd::Eof => 1,
+                                    // like being killed by SIGALRM
+                                    read::LineEnd::Timeout => 142,
+                                }
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "exec".to_string(),
+                    BuiltIn {
+                        name: "exec".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             _: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                // with no command only the redirects matter, the pipeline
+                                // keeps those around instead of restoring them.
+                                if args.is_empty() {
+                                    return 0;
+                                }
+                                let argv: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                let cargv = match argv
+                                    .iter()
+                                    .map(|arg| CString::new(arg.as_str()))
+                                    .collect::<Result<Vec<CString>, _>>()
+                                {
+                                    Ok(cargv) => cargv,
+                                    Err(_) => {
+                                        eprintln!("slush: exec: {}: invalid argument", argv[0]);
+                                        return 2;
+                                    }
+                                };
+                                let _ = io::stdout().flush();
+                                // execvp only ever returns on failure
+                                let Err(err) = nix::unistd::execvp(&cargv[0], &cargv);
+                                eprintln!("slush: exec: {}: {}", argv[0], err.desc());
+                                if err == nix::errno::Errno::ENOENT {
+                                    127
+                                } else {
+                                    126
+                                }
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "shift".to_string(),
+                    BuiltIn {
+                        name: "shift".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             _: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let count = match args.first().map(|arg| arg.eval(&state)) {
+                                    Some(n) => match n.parse::<usize>() {
+                                        Ok(n) => n,
+                                        Err(_) => {
+                                            eprintln!("slush: shift: Illegal number: {n}");
+                                            return 2;
+                                        }
+                                    },
+                                    None => 1,
+                                };
+                                let positionals = state.borrow().positionals();
+                                if count > positionals.len() {
+                                    eprintln!("slush: shift: can't shift that many");
+                                    return 1;
+                                }
+                                state
+                                    .borrow_mut()
+                                    .set_positionals(positionals[count..].to_vec());
+                                0
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "set".to_string(),
+                    BuiltIn {
+                        name: "set".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                if args.is_empty() {
+                                    if let Some(writer) = out {
+                                        let mut vars: Vec<(String, String)> = env::vars().collect();
+                                        vars.sort();
+                                        for (key, val) in vars {
+                                            slushwrite!(writer, "{}='{}'", key, val);
+                                        }
+                                    }
+                                    return 0;
+                                }
+                                let parsed = match options::SetArgs::parse(&args) {
+                                    Ok(parsed) => parsed,
+                                    Err(e) => {
+                                        eprintln!("slush: {e}");
+                                        return 2;
+                                    }
+                                };
+                                for (name, on) in &parsed.changes {
+                                    if let Err(e) = state.borrow_mut().options.set(name, *on) {
+                                        eprintln!("slush: {e}");
+                                        return 2;
+                                    }
+                                }
+                                if let Some(restorable) = parsed.show
+                                    && let Some(writer) = out
+                                {
+                                    for line in state.borrow().options.show(restorable) {
+                                        slushwrite!(writer, "{}", line);
+                                    }
+                                }
+                                if let Some(params) = parsed.positionals {
+                                    state.borrow_mut().set_positionals(
+                                        params.into_iter().map(Argument::Name).collect(),
+                                    );
+                                }
+                                0
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "getopts".to_string(),
+                    BuiltIn {
+                        name: "getopts".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
                              _: &mut Option<Box<dyn Write>>|
                              -> i32 {
-                                if let Some(stdin) = stdin {
-                                    let mut buffer = [0; 1];
-                                    let mut var = String::default();
-                                    while let Ok(count) = stdin.read(&mut buffer)
-                                        && count == 1
-                                        && buffer[0] as char != '\n'
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                if args.len() < 2 {
+                                    eprintln!(
+                                        "slush: getopts: usage: getopts optstring name [arg ...]"
+                                    );
+                                    return 2;
+                                }
+                                let (optstring, name) = (&args[0], &args[1]);
+                                let params: Vec<String> = if args.len() > 2 {
+                                    args[2..].to_vec()
+                                } else {
+                                    let positionals = state.borrow().positionals();
+                                    positionals.iter().map(|arg| arg.eval(&state)).collect()
+                                };
+                                let optind = env::var("OPTIND")
+                                    .ok()
+                                    .and_then(|optind| optind.parse().ok())
+                                    .unwrap_or(1);
+                                let silent = optstring.starts_with(':');
+                                let result =
+                                    state.borrow_mut().getopts.next(optstring, &params, optind);
+                                let (opt, optarg, status) = match result {
+                                    getopts::GetoptsResult::Opt(opt, optarg) => {
+                                        (opt.to_string(), optarg, 0)
+                                    }
+                                    getopts::GetoptsResult::Illegal(opt) if silent => {
+                                        (String::from("?"), Some(opt.to_string()), 0)
+                                    }
+                                    getopts::GetoptsResult::Illegal(opt) => {
+                                        eprintln!("slush: Illegal option -{opt}");
+                                        (String::from("?"), None, 0)
+                                    }
+                                    getopts::GetoptsResult::MissingArg(opt) if silent => {
+                                        (String::from(":"), Some(opt.to_string()), 0)
+                                    }
+                                    getopts::GetoptsResult::MissingArg(opt) => {
+                                        eprintln!("slush: No arg for -{opt} option");
+                                        (String::from("?"), None, 0)
+                                    }
+                                    getopts::GetoptsResult::Done => (String::from("?"), None, 1),
+                                };
+                                let state = state.borrow();
+                                let optind = state.getopts.optind().to_string();
+                                if let Err(e) = state
+                                    .set_variable(name, &opt)
+                                    .and_then(|_| state.set_variable("OPTIND", &optind))
+                                    .and_then(|_| match optarg {
+                                        Some(optarg) => state.set_variable("OPTARG", &optarg),
+                                        None => state.unset_variable("OPTARG"),
+                                    })
+                                {
+                                    eprintln!("slush: getopts: {e}");
+                                    return 2;
+                                }
+                                status
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "unset".to_string(),
+                    BuiltIn {
+                        name: "unset".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             _: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let mut args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                // only -f touches functions, without it names are variables
+                                let funcs = args.first().is_some_and(|a| a == "-f");
+                                if args.first().is_some_and(|a| a == "-f" || a == "-v") {
+                                    args.remove(0);
+                                }
+                                let vars = !funcs;
+                                let mut ret = 0;
+                                for name in args {
+                                    if vars && let Err(e) = state.borrow().unset_variable(&name) {
+                                        eprintln!("slush: unset: {e}");
+                                        ret = 1;
+                                        continue;
+                                    }
+                                    if funcs {
+                                        state.borrow_mut().functions.remove(&name);
+                                    }
+                                }
+                                ret
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "readonly".to_string(),
+                    BuiltIn {
+                        name: "readonly".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                if args.is_empty() || args[0] == "-p" {
+                                    if let Some(writer) = out {
+                                        let mut names: Vec<String> =
+                                            state.borrow().readonly.iter().cloned().collect();
+                                        names.sort();
+                                        for name in names {
+                                            match env::var(&name) {
+                                                Ok(val) => {
+                                                    slushwrite!(
+                                                        writer,
+                                                        "readonly {}='{}'",
+                                                        name,
+                                                        val
+                                                    );
+                                                }
+                                                Err(_) => {
+                                                    slushwrite!(writer, "readonly {}", name);
+                                                }
+                                            }
+                                        }
+                                    }
+                                    return 0;
+                                }
+                                let mut ret = 0;
+                                for arg in args {
+                                    let (name, val) = match arg.split_once('=') {
+                                        Some((name, val)) => (name, Some(val)),
+                                        None => (arg.as_str(), None),
+                                    };
+                                    let result = match val {
+                                        Some(val) => state.borrow().set_variable(name, val),
+                                        None if is_valid_name(name) => Ok(()),
+                                        None => Err(format!("{name}: bad variable name")),
+                                    };
+                                    match result {
+                                        Ok(()) => {
+                                            state.borrow_mut().readonly.insert(name.to_string());
+                                        }
+                                        Err(e) => {
+                                            eprintln!("slush: readonly: {e}");
+                                            ret = 1;
+                                        }
+                                    }
+                                }
+                                ret
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "local".to_string(),
+                    BuiltIn {
+                        name: "local".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             _: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                if state.borrow().local_scopes.is_empty() {
+                                    eprintln!("slush: local: not in a function");
+                                    return 2;
+                                }
+                                let mut ret = 0;
+                                for arg in args.iter().map(|arg| arg.eval(&state)) {
+                                    let (name, val) = match arg.split_once('=') {
+                                        Some((name, val)) => (name, Some(val)),
+                                        None => (arg.as_str(), None),
+                                    };
+                                    // without a value the local starts off with the value it shadows
+                                    let mut result = state.borrow_mut().make_local(name);
+                                    if let (Ok(()), Some(val)) = (&result, val) {
+                                        result = state.borrow().set_variable(name, val);
+                                    }
+                                    if let Err(e) = result {
+                                        eprintln!("slush: local: {e}");
+                                        ret = 1;
+                                    }
+                                }
+                                ret
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "alias".to_string(),
+                    BuiltIn {
+                        name: "alias".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                let mut names: Vec<String> = args.clone();
+                                if args.is_empty() {
+                                    names = state.borrow().aliases.keys().cloned().collect();
+                                    names.sort();
+                                }
+                                let mut ret = 0;
+                                for arg in names {
+                                    if let Some((name, value)) = arg.split_once('=') {
+                                        state
+                                            .borrow_mut()
+                                            .aliases
+                                            .insert(name.to_string(), value.to_string());
+                                    } else if let Some(value) = state.borrow().aliases.get(&arg) {
+                                        if let Some(writer) = out {
+                                            slushwrite!(writer, "{}='{}'", arg, value);
+                                        }
+                                    } else {
+                                        eprintln!("slush: alias: {arg} not found");
+                                        ret = 1;
+                                    }
+                                }
+                                ret
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "unalias".to_string(),
+                    BuiltIn {
+                        name: "unalias".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             _: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                if args.first().is_some_and(|arg| arg == "-a") {
+                                    state.borrow_mut().aliases.clear();
+                                    return 0;
+                                }
+                                let mut ret = 0;
+                                for name in args {
+                                    if state.borrow_mut().aliases.remove(&name).is_none() {
+                                        eprintln!("slush: unalias: {name} not found");
+                                        ret = 1;
+                                    }
+                                }
+                                ret
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "command".to_string(),
+                    BuiltIn {
+                        name: "command".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                // `command name` is run by the pipeline, only the
+                                // -v and -V queries end up here
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                let mut verbose = None;
+                                let mut path = env::var("PATH").unwrap_or_default();
+                                let mut names = args.as_slice();
+                                while let Some(opt) = names.first()
+                                    && opt.starts_with('-')
+                                    && opt.len() > 1
+                                {
+                                    names = &names[1..];
+                                    if opt == "--" {
+                                        break;
+                                    }
+                                    for c in opt.chars().skip(1) {
+                                        match c {
+                                            'p' => path = lookup::DEFAULT_PATH.to_string(),
+                                            'v' => verbose = Some(false),
+                                            'V' => verbose = Some(true),
+                                            _ => {
+                                                eprintln!("slush: command: -{c}: invalid option");
+                                                return 2;
+                                            }
+                                        }
+                                    }
+                                }
+                                let Some(verbose) = verbose else {
+                                    return 0;
+                                };
+                                let mut ret = 0;
+                                for name in names {
+                                    let kind = lookup::command_type(name, &state.borrow(), &path);
+                                    let Some(writer) = out else {
+                                        continue;
+                                    };
+                                    match kind {
+                                        Some(kind) if verbose => {
+                                            slushwrite!(
+                                                writer,
+                                                "{}",
+                                                lookup::describe(name, &kind)
+                                            );
+                                        }
+                                        Some(lookup::CommandType::Alias(value)) => {
+                                            slushwrite!(writer, "alias {}='{}'", name, value);
+                                        }
+                                        Some(lookup::CommandType::File(file)) => {
+                                            slushwrite!(writer, "{}", file.display());
+                                        }
+                                        Some(_) => {
+                                            slushwrite!(writer, "{}", name);
+                                        }
+                                        None if verbose => {
+                                            slushwrite!(writer, "{}: not found", name);
+                                            ret = 127;
+                                        }
+                                        None => ret = 127,
+                                    }
+                                }
+                                ret
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "hash".to_string(),
+                    BuiltIn {
+                        name: "hash".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                if args.is_empty() {
+                                    if let Some(writer) = out {
+                                        for file in state.borrow().path_cache.entries() {
+                                            slushwrite!(writer, "{}", file.display());
+                                        }
+                                    }
+                                    return 0;
+                                }
+                                let mut names = args.as_slice();
+                                if names[0] == "-r" {
+                                    state.borrow_mut().path_cache.clear();
+                                    names = &names[1..];
+                                }
+                                let path = env::var("PATH").unwrap_or_default();
+                                let mut ret = 0;
+                                for name in names {
+                                    // builtins and functions are never looked up on PATH
+                                    let state = &mut *state.borrow_mut();
+                                    if state.built_ins.contains_key(name)
+                                        || state.functions.contains_key(name)
                                     {
-                                        var.push(buffer[0] as char);
+                                        continue;
                                     }
-                                    for arg in args {
-                                        unsafe {
-                                            std::env::set_var(arg.eval(&state), &var);
+                                    if state.path_cache.find(name, &path).is_err() {
+                                        eprintln!("slush: hash: {name}: not found");
+                                        ret = 1;
+                                    }
+                                }
+                                ret
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "umask".to_string(),
+                    BuiltIn {
+                        name: "umask".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                let (symbolic, mode) = match args.as_slice() {
+                                    [] => (false, None),
+                                    [opt] if opt == "-S" => (true, None),
+                                    [opt, mode] if opt == "-S" => (true, Some(mode)),
+                                    [mode] => (false, Some(mode)),
+                                    _ => {
+                                        eprintln!("slush: umask: too many arguments");
+                                        return 2;
+                                    }
+                                };
+                                // the only way to read the mask is to set it
+                                let current = stat::umask(stat::Mode::empty());
+                                stat::umask(current);
+                                let Some(mode) = mode else {
+                                    if let Some(writer) = out {
+                                        if symbolic {
+                                            slushwrite!(
+                                                writer,
+                                                "{}",
+                                                umask::format_symbolic(current.bits())
+                                            );
+                                        } else {
+                                            slushwrite!(
+                                                writer,
+                                                "{}",
+                                                umask::format_octal(current.bits())
+                                            );
                                         }
                                     }
-                                    0
+                                    return 0;
+                                };
+                                match umask::parse(mode, current.bits()) {
+                                    Ok(mask) => {
+                                        stat::umask(stat::Mode::from_bits_truncate(mask));
+                                        0
+                                    }
+                                    Err(e) => {
+                                        eprintln!("slush: {e}");
+                                        1
+                                    }
+                                }
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "ulimit".to_string(),
+                    BuiltIn {
+                        name: "ulimit".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                let args = match resources::UlimitArgs::parse(&args) {
+                                    Ok(args) => args,
+                                    Err(e) => {
+                                        eprintln!("slush: {e}");
+                                        return 2;
+                                    }
+                                };
+                                let limits: &[resources::Limit] = if args.all {
+                                    &resources::LIMITS
                                 } else {
-                                    1
+                                    std::slice::from_ref(args.limit)
+                                };
+                                if let Some(value) = &args.value {
+                                    if let Err(e) = args.limit.set(value, args.hard, args.soft) {
+                                        eprintln!("slush: {e}");
+                                        return 1;
+                                    }
+                                    return 0;
+                                }
+                                for limit in limits {
+                                    let value = match limit.show(args.hard) {
+                                        Ok(value) => value,
+                                        Err(e) => {
+                                            eprintln!("slush: {e}");
+                                            return 1;
+                                        }
+                                    };
+                                    let Some(writer) = out else {
+                                        continue;
+                                    };
+                                    if args.all {
+                                        slushwrite!(writer, "{:<20} {}", limit.name, value);
+                                    } else {
+                                        slushwrite!(writer, "{}", value);
+                                    }
+                                }
+                                0
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "times".to_string(),
+                    BuiltIn {
+                        name: "times".to_string(),
+                        command: Rc::new(
+                            |_: &Vec<Argument>,
+                             _: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                // the shell itself first, then everything it has waited on
+                                for who in [UsageWho::RUSAGE_SELF, UsageWho::RUSAGE_CHILDREN] {
+                                    let usage = match getrusage(who) {
+                                        Ok(usage) => usage,
+                                        Err(e) => {
+                                            eprintln!("slush: times: {e}");
+                                            return 1;
+                                        }
+                                    };
+                                    if let Some(writer) = out {
+                                        slushwrite!(
+                                            writer,
+                                            "{} {}",
+                                            resources::format_cpu_time(usage.user_time()),
+                                            resources::format_cpu_time(usage.system_time())
+                                        );
+                                    }
                                 }
+                                0
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "type".to_string(),
+                    BuiltIn {
+                        name: "type".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let path = env::var("PATH").unwrap_or_default();
+                                let mut ret = 0;
+                                for arg in args {
+                                    let name = arg.eval(&state);
+                                    let kind = lookup::command_type(&name, &state.borrow(), &path);
+                                    let Some(writer) = out else {
+                                        continue;
+                                    };
+                                    match kind {
+                                        Some(kind) => {
+                                            slushwrite!(
+                                                writer,
+                                                "{}",
+                                                lookup::describe(&name, &kind)
+                                            );
+                                        }
+                                        None => {
+                                            slushwrite!(writer, "{}: not found", name);
+                                            ret = 127;
+                                        }
+                                    }
+                                }
+                                ret
                             },
                         ),
                     },
@@ -205,11 +1106,46 @@ impl State {
                              _: &mut Option<Box<dyn Write>>|
                              -> i32 {
                                 if !args.is_empty() {
-                                    std::process::exit(
+                                    trap::exit(
+                                        &state,
                                         args[0].eval(&state).parse().unwrap_or_default(),
                                     );
                                 } else {
-                                    std::process::exit(0);
+                                    trap::exit(&state, 0);
+                                }
+                            },
+                        ),
+                    },
+                ),
+                (
+                    "trap".to_string(),
+                    BuiltIn {
+                        name: "trap".to_string(),
+                        command: Rc::new(
+                            |args: &Vec<Argument>,
+                             state: Rc<RefCell<State>>,
+                             _: &mut Option<Box<dyn Read>>,
+                             out: &mut Option<Box<dyn Write>>|
+                             -> i32 {
+                                let args: Vec<String> =
+                                    args.iter().map(|arg| arg.eval(&state)).collect();
+                                match trap::parse(&args) {
+                                    Ok(trap::TrapArgs::Show) => {
+                                        if let Some(action) = &state.borrow().exit_trap
+                                            && let Some(writer) = out
+                                        {
+                                            slushwrite!(writer, "{}", trap::show(action));
+                                        }
+                                        0
+                                    }
+                                    Ok(trap::TrapArgs::Set(action)) => {
+                                        state.borrow_mut().exit_trap = action;
+                                        0
+                                    }
+                                    Err(e) => {
+                                        eprintln!("slush: {e}");
+                                        1
+                                    }
                                 }
                             },
                         ),
@@ -220,6 +1156,134 @@ impl State {
     }
 }
 
+impl State {
+    // the positional parameters of the innermost function call, or the script itself
+    pub fn positionals(&self) -> Rc<Vec<Argument>> {
+        self.argstack.borrow().last().cloned().unwrap_or_default()
+    }
+
+    pub fn set_positionals(&mut self, args: Vec<Argument>) {
+        let mut argstack = self.argstack.borrow_mut();
+        match argstack.last_mut() {
+            Some(frame) => *frame = Rc::new(args),
+            None => argstack.push(Rc::new(args)),
+        }
+    }
+
+    // Once an expansion has failed the command it was part of must not run.
+    // This is checked after expanding words and the error travels up to
+    // whoever runs whole commands, a script exits and the prompt goes on to
+    // the next line. The message was already printed when it failed.
+    pub fn check_expansion(&self) -> Result<(), String> {
+        if self.expansion_failed {
+            return Err(String::new());
+        }
+        Ok(())
+    }
+
+    // every assignment the shell makes goes through here so readonly is respected
+    pub fn set_variable(&self, key: &str, val: &str) -> Result<(), String> {
+        if !is_valid_name(key) {
+            return Err(format!("{key}: bad variable name"));
+        }
+        if self.readonly.contains(key) {
+            return Err(format!("{key}: is read only"));
+        }
+        // these are worked out on every read, assigning only resets them
+        match key {
+            "RANDOM" => self.random.seed(val.parse().unwrap_or(0)),
+            "SECONDS" => self.seconds.reset(val.parse().unwrap_or(0)),
+            _ => unsafe {
+                env::set_var(key, val);
+            },
+        }
+        Ok(())
+    }
+
+    pub fn unset_variable(&self, key: &str) -> Result<(), String> {
+        if self.readonly.contains(key) {
+            return Err(format!("{key}: is read only"));
+        }
+        if is_valid_name(key) {
+            unsafe {
+                env::remove_var(key);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    // Locals are dynamically scoped like dash and bash, the variable itself is
+    // simply overwritten and the value it shadowed is put back when the
+    // function that declared it returns. Callees see the caller's locals.
+    pub fn make_local(&mut self, key: &str) -> Result<(), String> {
+        if !is_valid_name(key) {
+            return Err(format!("{key}: bad variable name"));
+        }
+        if self.readonly.contains(key) {
+            return Err(format!("{key}: is read only"));
+        }
+        if let Some(scope) = self.local_scopes.last_mut() {
+            scope
+                .entry(key.to_string())
+                .or_insert_with(|| env::var(key).ok());
+        }
+        Ok(())
+    }
+
+    pub fn pop_local_scope(&mut self) {
+        if let Some(scope) = self.local_scopes.pop() {
+            for (key, val) in scope {
+                unsafe {
+                    match val {
+                        Some(val) => env::set_var(key, val),
+                        None => env::remove_var(key),
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The builtins whose prefix assignments stay in the shell after they finish.
+const SPECIAL_BUILTINS: [&str; 7] = ["exec", "exit", "readonly", "set", "shift", "times", "unset"];
+
+// Variables assigned in front of a command, `IFS=: read a b`, only last for
+// that command. The values they had are put back when this is dropped.
+#[derive(Default)]
+struct SavedVariables {
+    saved: Vec<(String, Option<String>)>,
+}
+
+impl SavedVariables {
+    fn save(&mut self, key: &str) {
+        self.saved.push((key.to_string(), env::var(key).ok()));
+    }
+
+    fn forget(&mut self, key: &str) {
+        self.saved.retain(|(saved, _)| saved != key);
+    }
+}
+
+impl Drop for SavedVariables {
+    fn drop(&mut self) {
+        while let Some((key, val)) = self.saved.pop() {
+            unsafe {
+                match val {
+                    Some(val) => env::set_var(key, val),
+                    None => env::remove_var(key),
+                }
+            }
+        }
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
 // sort of a hack to always assume all states are the same ? seems JANK
 impl PartialEq for State {
     fn eq(&self, _: &Self) -> bool {
@@ -260,6 +1324,8 @@ pub enum RedirectType {
     Out,
     OutAppend,
     In,
+    OutDup, // >&
+    InDup,  // <&
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -276,24 +1342,22 @@ pub struct CommandExpr {
     pub command: Argument,
     pub arguments: Rc<Vec<Argument>>,
     pub assignment: Option<AssignmentExpr>,
+    pub redirects: Vec<RedirectExpr>,
 }
 
 #[derive(Debug, Clone)]
 pub struct PipeLineExpr {
     pub pipeline: Vec<CompoundList>,
-    pub capture_out: Option<Rc<RefCell<String>>>,
-    pub file_redirect: Option<RedirectExpr>,
-    pub pipe_in: Option<Rc<RefCell<(PipeReader, PipeWriter)>>>,
     pub background: bool,
     pub state: Rc<RefCell<State>>,
+    pub line: usize, // where the pipeline starts, for $LINENO
 }
 
 impl PartialEq for PipeLineExpr {
     fn eq(&self, other: &Self) -> bool {
         self.pipeline == other.pipeline
-            && self.capture_out == other.capture_out
-            && self.file_redirect == other.file_redirect
             && self.background == other.background
+            && self.line == other.line
     }
 }
 
@@ -340,13 +1404,7 @@ pub struct WhileExpr {
 }
 
 impl WhileExpr {
-    pub fn eval(
-        &mut self,
-        input: Option<Rc<RefCell<(PipeReader, PipeWriter)>>>,
-    ) -> Result<i32, String> {
-        if let Some(pipe_in) = input {
-            self.condition.pipe_in(pipe_in);
-        }
+    pub fn eval(&mut self) -> Result<i32, String> {
         while self.condition.eval()? == 0 {
             for command in &mut self.body {
                 command.eval()?;
@@ -366,10 +1424,14 @@ pub struct ForExpr {
 impl ForExpr {
     pub fn eval(&mut self, state: &Rc<RefCell<State>>) -> Result<i32, String> {
         let mut ret = 0;
-        for arg in &self.list {
-            unsafe {
-                env::set_var(&self.name, arg.eval(state));
-            }
+        let list: Vec<String> = self
+            .list
+            .iter()
+            .flat_map(|arg| arg.eval_fields(state))
+            .collect();
+        state.borrow().check_expansion()?;
+        for val in list {
+            state.borrow().set_variable(&self.name, &val)?;
             for command in &mut self.commands {
                 ret = command.eval()?;
             }
@@ -393,6 +1455,35 @@ impl NotExpr {
     }
 }
 
+// `time [-p] pipeline`, reports how long the pipeline took on stderr
+#[derive(Debug, PartialEq, Clone)]
+pub struct TimeExpr {
+    pub pipeline: PipeLineExpr,
+    pub posix: bool,
+}
+
+impl TimeExpr {
+    pub fn eval(&mut self) -> Result<i32, String> {
+        let start = Instant::now();
+        let before = resources::cpu_usage();
+        let status = self.pipeline.eval();
+        let after = resources::cpu_usage();
+        let real = start.elapsed().as_secs_f64();
+        let format = match env::var("TIMEFORMAT") {
+            _ if self.posix => resources::POSIX_TIMEFORMAT.to_string(),
+            Ok(format) => format,
+            Err(_) => resources::DEFAULT_TIMEFORMAT.to_string(),
+        };
+        if !format.is_empty() {
+            eprintln!(
+                "{}",
+                resources::format_timing(&format, real, after.0 - before.0, after.1 - before.1)
+            );
+        }
+        status
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum CompoundList {
     Ifexpr(IfExpr),
@@ -401,6 +1492,34 @@ pub enum CompoundList {
     Commandexpr(CommandExpr),
     Functionexpr(FunctionExpr),
     CommandBlock(Vec<AndOrNode>),
+    // a compound command followed by redirects, `done < list`
+    Redirected(Box<CompoundList>, Vec<RedirectExpr>),
+}
+
+impl CompoundList {
+    // runs an if, while, for or { } block in the shell itself, whatever the
+    // pipeline connected it to is already on fds 0 and 1
+    fn eval_compound(&mut self, state: &Rc<RefCell<State>>) -> Result<i32, String> {
+        match self {
+            CompoundList::Ifexpr(ifxpr) => ifxpr.eval(),
+            CompoundList::Whileexpr(whlexpr) => whlexpr.eval(),
+            CompoundList::Forexpr(forexpr) => forexpr.eval(state),
+            CompoundList::CommandBlock(block) => {
+                let mut ret = 0;
+                for command in block {
+                    ret = command.eval()?;
+                }
+                Ok(ret)
+            }
+            CompoundList::Redirected(compound, redirects) => {
+                let actions = redirect::resolve(redirects, state)?;
+                let _saved = redirect::SavedFds::apply(&actions)?;
+                compound.eval_compound(state)
+            }
+            CompoundList::Functionexpr(func) => Ok(func.eval(state)),
+            CompoundList::Commandexpr(_) => Ok(0),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -409,29 +1528,17 @@ pub enum AndOrNode {
     Andif(Box<AndIf>),
     Orif(Box<OrIf>),
     Notif(Box<NotExpr>),
+    Time(Box<TimeExpr>),
 }
 
 impl AndOrNode {
-    pub fn pipe_in(&mut self, pipe: Rc<RefCell<(PipeReader, PipeWriter)>>) {
-        if let AndOrNode::Pipeline(node) = self {
-            node.pipe_in = Some(pipe)
-        };
-    }
     pub fn eval(&mut self) -> Result<i32, String> {
         match self {
             AndOrNode::Pipeline(pl) => pl.eval(),
             AndOrNode::Andif(and) => and.eval(),
             AndOrNode::Orif(or) => or.eval(),
             AndOrNode::Notif(not) => not.eval(),
-        }
-    }
-
-    pub fn set_output_capture(&mut self, capture: Rc<RefCell<String>>) {
-        match self {
-            AndOrNode::Pipeline(pl) => pl.set_output_capture(capture),
-            AndOrNode::Andif(and) => and.set_output_capture(capture),
-            AndOrNode::Orif(or) => or.set_output_capture(capture),
-            AndOrNode::Notif(not) => not.condition.set_output_capture(capture),
+            AndOrNode::Time(time) => time.eval(),
         }
     }
 }
@@ -450,11 +1557,6 @@ impl OrIf {
         }
         Ok(ll)
     }
-
-    pub fn set_output_capture(&mut self, capture: Rc<RefCell<String>>) {
-        self.left.set_output_capture(capture.clone());
-        self.right.set_output_capture(capture.clone());
-    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -472,11 +1574,6 @@ impl AndIf {
         let rr = self.right.eval()?;
         Ok(rr)
     }
-
-    pub fn set_output_capture(&mut self, capture: Rc<RefCell<String>>) {
-        self.left.set_output_capture(capture.clone()); // Line 123 !
-        self.right.set_output_capture(capture.clone());
-    }
 }
 
 // pub struct And IF
@@ -495,23 +1592,83 @@ pub struct SubShellExpr {
 
 impl AssignmentExpr {
     fn eval(&mut self, state: &Rc<RefCell<State>>) -> i32 {
-        unsafe {
-            env::set_var(&self.key, self.val.eval(state));
+        let val = self.val.eval(state);
+        if state.borrow().expansion_failed {
+            return 1;
+        }
+        if let Err(e) = state.borrow().set_variable(&self.key, &val) {
+            eprintln!("slush: {e}");
+            return 1;
         }
+        prompt::trace(&[format!("{}={val}", self.key)], state);
         0
     }
 }
 
 impl SubShellExpr {
-    pub fn stdout(&self) -> String {
-        let mut parser = Parser::new(State::new());
-        let shell_output: Rc<RefCell<String>> = Default::default();
-        parser.parse(&self.shell);
-        for mut expr in parser.exprs {
-            expr.set_output_capture(shell_output.clone());
-            let _ = expr.eval();
+    // The commands run in a forked copy of the shell with fd 1 on a pipe, so
+    // everything they write ends up in the output, builtins and every stage
+    // of a pipeline included, while their assignments stay in the copy.
+    // Their status becomes $?, which is what a command made of nothing but
+    // assignments exits with. Words are strings, so bytes that aren't UTF-8
+    // come out as U+FFFD rather than as they were.
+    pub fn stdout(&self, state: &Rc<RefCell<State>>) -> String {
+        let (mut reader, writer) = match pipe() {
+            Ok(ends) => ends,
+            Err(e) => {
+                eprintln!("slush: Error creating pipe: {e}");
+                return String::new();
+            }
+        };
+        let _ = io::stdout().flush();
+        match unsafe { fork() } {
+            Ok(ForkResult::Parent { child }) => {
+                drop(writer);
+                let mut output = Vec::new();
+                let _ = reader.read_to_end(&mut output);
+                drop(reader);
+                let status = SlushJob::new(SlushJobType::Forked(child), None, None).wait();
+                state.borrow_mut().prev_status = status;
+                state.borrow_mut().substituted = true;
+                // all trailing newlines go, not just the last one
+                while output.last() == Some(&b'\n') {
+                    output.pop();
+                }
+                String::from_utf8_lossy(&output).into_owned()
+            }
+            Ok(ForkResult::Child) => {
+                unsafe {
+                    libc::dup2(writer.as_raw_fd(), 1);
+                }
+                drop(writer);
+                drop(reader);
+                // a subshell starts without the traps of the shell it came from
+                state.borrow_mut().exit_trap = None;
+                let mut parser = Parser::new(state.clone());
+                parser.first_line = state.borrow().lineno;
+                parser.parse(&self.shell);
+                if !parser.err.is_empty() {
+                    eprintln!("slush: {}", parser.err);
+                    process::exit(2);
+                }
+                for mut expr in parser.exprs {
+                    match expr.eval() {
+                        Ok(status) => state.borrow_mut().prev_status = status,
+                        Err(_) if state.borrow().expansion_failed => trap::exit(state, 2),
+                        Err(err) => {
+                            eprintln!("{err}");
+                            state.borrow_mut().prev_status = 1;
+                        }
+                    }
+                }
+                let status = state.borrow().prev_status;
+                trap::exit(state, status);
+            }
+            Err(e) => {
+                eprintln!("slush: fork: {e}");
+                String::new()
+            }
         }
-        shell_output.borrow().clone()
     }
 }
 
@@ -520,20 +1677,35 @@ impl CommandExpr {
         let com = self.command.eval(state);
         let mut parts = vec![com];
         for arg in &*self.arguments {
-            parts.push(arg.eval(state));
+            parts.extend(arg.eval_fields(state));
+        }
+        CommandStr {
+            parts,
+            program: None,
         }
-        CommandStr { parts }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct CommandStr {
     parts: Vec<String>,
+    program: Option<PathBuf>, // where the command was found, if it was looked up
 }
 
 impl CommandStr {
+    pub fn name(&self) -> &str {
+        &self.parts[0]
+    }
+
     pub fn build_command(&self) -> Box<Command> {
-        let mut cmd = Box::new(Command::new(&self.parts[0]));
+        let mut cmd = match &self.program {
+            Some(program) => {
+                let mut cmd = Box::new(Command::new(program));
+                cmd.arg0(&self.parts[0]);
+                cmd
+            }
+            None => Box::new(Command::new(&self.parts[0])),
+        };
         for arg in &self.parts[1..] {
             cmd.arg(arg);
         }
@@ -571,9 +1743,12 @@ impl Debug for BuiltIn {
 
 #[derive(Debug, Clone)]
 enum SlushJobType {
-    Builtin(BuiltIn, Rc<Vec<Argument>>),
-    Function(String, Rc<Vec<Argument>>),
+    Builtin(BuiltIn, Rc<Vec<Argument>>, Vec<RedirectExpr>),
+    Function(String, Rc<Vec<Argument>>, Vec<RedirectExpr>),
+    Compound(CompoundList),
     Child(Arc<SharedChild>),
+    Forked(Pid), // a copy of the shell running one of the above
+    Exited(i32), // finished before it got started
 }
 
 #[derive(Debug)]
@@ -595,10 +1770,176 @@ impl SlushJob {
             stdout,
         }
     }
+
+    // Runs a builtin, function or compound command in a forked copy of the
+    // shell so it can stream alongside the rest of the pipeline. The child
+    // closes every pipe it doesn't use, otherwise readers further down never
+    // see EOF.
+    fn fork(
+        self,
+        pipes: &[Rc<RefCell<(PipeReader, PipeWriter)>>],
+        state: &Rc<RefCell<State>>,
+    ) -> Result<SlushJob, String> {
+        let _ = io::stdout().flush();
+        match unsafe { fork() } {
+            Ok(ForkResult::Parent { child }) => {
+                Ok(SlushJob::new(SlushJobType::Forked(child), None, None))
+            }
+            Ok(ForkResult::Child) => {
+                unsafe {
+                    let _ = signal::signal(Signal::SIGINT, SigHandler::SigDfl);
+                    let _ = signal::signal(Signal::SIGPIPE, SigHandler::SigDfl);
+                    if let Some(stdin) = &self.stdin {
+                        libc::dup2(stdin.as_raw_fd(), 0);
+                    }
+                    if let Some(stdout) = &self.stdout {
+                        libc::dup2(stdout.as_raw_fd(), 1);
+                    }
+                    for pipe in pipes {
+                        let pipe = pipe.borrow();
+                        libc::close(pipe.0.as_raw_fd());
+                        libc::close(pipe.1.as_raw_fd());
+                    }
+                }
+                state.borrow_mut().exit_trap = None;
+                let status = self.run_in_shell(state).unwrap_or_else(|err| {
+                    // a failed expansion already said what went wrong
+                    if state.borrow().expansion_failed {
+                        return 2;
+                    }
+                    eprintln!("{err}");
+                    1
+                });
+                trap::exit(state, status);
+            }
+            Err(e) => Err(format!("slush: fork: {e}")),
+        }
+    }
+
+    // runs a builtin, function or compound command inside this shell process
+    fn run_in_shell(&self, state: &Rc<RefCell<State>>) -> Result<i32, String> {
+        let mut exit_status = 0;
+        match &self.jobtype {
+            SlushJobType::Builtin(builtin, args, redirects) => {
+                // expand the words up front so a failed ${name?} stops the builtin
+                let args: Vec<Argument> = args
+                    .iter()
+                    .flat_map(|a| a.eval_fields(state))
+                    .map(Argument::Name)
+                    .collect();
+                state.borrow().check_expansion()?;
+                trace_call(&builtin.name, &args, state);
+                let mut actions = match redirect::resolve(redirects, state) {
+                    Ok(actions) => actions,
+                    // exec is a special builtin, a redirect it can't make ends a
+                    // script the way a failed ${name?word} does
+                    Err(err) if builtin.name == "exec" && args.is_empty() => {
+                        eprintln!("{err}");
+                        state.borrow_mut().expansion_failed = true;
+                        return Err(String::new());
+                    }
+                    Err(err) => return Err(err),
+                };
+                // input from the pipeline goes on fd 0 as well, any redirect of
+                // fd 0 comes after it so it still wins
+                if let Some(reader) = &self.stdin {
+                    actions.insert(0, redirect::FdAction::Copy(reader.as_raw_fd(), 0));
+                }
+                let saved = redirect::SavedFds::apply(&actions)?;
+                // a redirected builtin reads and writes the shell's own (now redirected)
+                // stdin and stdout instead of whatever the pipeline handed it.
+                let mut boxed_reader: Option<Box<dyn Read>> = if redirect::redirects_fd(&actions, 0)
+                {
+                    redirect::dup_fd(0).map(|fd| Box::new(File::from(fd)) as Box<dyn Read>)
+                } else {
+                    self.stdin
+                        .as_ref()
+                        .map(|reader| Box::new(reader.try_clone().unwrap()) as Box<dyn Read>)
+                };
+                let mut boxed_writer: Option<Box<dyn Write>> =
+                    if redirect::redirects_fd(&actions, 1) {
+                        redirect::dup_fd(1).map(|fd| Box::new(File::from(fd)) as Box<dyn Write>)
+                    } else {
+                        self.stdout
+                            .as_ref()
+                            .map(|writer| Box::new(writer.try_clone().unwrap()) as Box<dyn Write>)
+                    };
+                exit_status =
+                    (builtin.command)(&args, state.clone(), &mut boxed_reader, &mut boxed_writer);
+                drop(boxed_writer);
+                // exec without a command makes its redirects permanent for the shell
+                if builtin.name == "exec" && args.is_empty() {
+                    saved.persist();
+                }
+            }
+            SlushJobType::Function(function, args, redirects) => {
+                let aa = args
+                    .iter()
+                    .flat_map(|a| a.eval_fields(state))
+                    .map(Argument::Name)
+                    .collect::<Vec<Argument>>();
+                state.borrow().check_expansion()?;
+                trace_call(function, &aa, state);
+                let mut actions = redirect::resolve(redirects, state)?;
+                if let Some(reader) = &self.stdin {
+                    actions.insert(0, redirect::FdAction::Copy(reader.as_raw_fd(), 0));
+                }
+                let _saved = redirect::SavedFds::apply(&actions)?;
+                let argstack = state.borrow().argstack.clone();
+                argstack.borrow_mut().push(Rc::new(aa));
+                state.borrow_mut().local_scopes.push(HashMap::new());
+                let pl = state.borrow().functions.get(function).cloned();
+                let mut status = Ok(0);
+                if let Some(pl) = pl {
+                    // evaluate a copy of the body so the function can call itself
+                    let mut body = pl.borrow().clone();
+                    for pipeline in body.iter_mut() {
+                        status = pipeline.eval();
+                        if status.is_err() {
+                            break;
+                        }
+                    }
+                }
+
+                state.borrow_mut().pop_local_scope();
+                argstack.borrow_mut().pop();
+                exit_status = status?;
+            }
+            SlushJobType::Compound(compound) => {
+                let actions = match &self.stdin {
+                    Some(reader) => vec![redirect::FdAction::Copy(reader.as_raw_fd(), 0)],
+                    None => Vec::new(),
+                };
+                let _saved = redirect::SavedFds::apply(&actions)?;
+                exit_status = compound.clone().eval_compound(state)?;
+            }
+            SlushJobType::Exited(status) => exit_status = *status,
+            SlushJobType::Child(_) | SlushJobType::Forked(_) => {}
+        }
+        Ok(exit_status)
+    }
+
+    fn wait(&self) -> i32 {
+        match &self.jobtype {
+            SlushJobType::Child(child) => child
+                .wait()
+                .map_or(1, |status| status.code().unwrap_or(130)),
+            SlushJobType::Forked(pid) => match waitpid(*pid, None) {
+                Ok(WaitStatus::Exited(_, status)) => status,
+                Ok(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+                _ => 1,
+            },
+            SlushJobType::Exited(status) => *status,
+            SlushJobType::Builtin(..) | SlushJobType::Function(..) | SlushJobType::Compound(_) => 0,
+        }
+    }
 }
 
 impl PipeLineExpr {
-    fn assemble_pipeline(&mut self) -> Result<Vec<SlushJob>, String> {
+    fn assemble_pipeline(
+        &mut self,
+        prefixed: &mut SavedVariables,
+    ) -> Result<Vec<SlushJob>, String> {
         let mut jobs: Vec<SlushJob> = Vec::new();
         let mut pipes: Vec<Rc<RefCell<(PipeReader, PipeWriter)>>> = Vec::new();
         let sz = self.pipeline.len();
@@ -609,120 +1950,208 @@ impl PipeLineExpr {
             });
         }
         for (i, expr) in self.pipeline.iter_mut().enumerate() {
+            let mut input_pipe = None;
+            if i > 0
+                && let Some(pipeline) = pipes.get_mut(i)
+            {
+                input_pipe = Some(
+                    pipeline
+                        .borrow_mut()
+                        .0
+                        .try_clone()
+                        .expect("Error cloning pipe"),
+                );
+            }
+            let output_pipe: Option<PipeWriter>;
+            if let Some(pipeline) = pipes.get_mut(i + 1) {
+                output_pipe = Some(
+                    pipeline
+                        .borrow_mut()
+                        .1
+                        .try_clone()
+                        .expect("Error cloning pipe"),
+                );
+            } else {
+                // close on exec, so programs run by exec don't inherit a copy
+                let p = io::stdout().as_raw_fd();
+                unsafe {
+                    let dup_fd = libc::fcntl(p, libc::F_DUPFD_CLOEXEC, 0);
+                    output_pipe = Some(PipeWriter::from(OwnedFd::from_raw_fd(dup_fd)));
+                }
+            }
+
             match expr {
-                CompoundList::Ifexpr(ifxpr) => ifxpr.eval()?,
-                CompoundList::Whileexpr(whlexpr) => whlexpr.eval(None)?,
-                CompoundList::Forexpr(forexpr) => forexpr.eval(&self.state.clone())?,
-                CompoundList::Functionexpr(func) => func.eval(&self.state.clone()),
-                CompoundList::CommandBlock(block) => {
-                    let mut ret = 0;
-                    for command in block {
-                        ret = command.eval()?;
-                    }
-                    ret
+                CompoundList::Functionexpr(func) => {
+                    func.eval(&self.state.clone());
                 }
                 CompoundList::Commandexpr(exp) => {
-                    if let Some(ref mut ass) = exp.assignment {
-                        ass.eval(&self.state.clone());
+                    self.state.borrow_mut().substituted = false;
+                    let has_command =
+                        !matches!(&exp.command, Argument::Name(arg) if arg.is_empty());
+                    if let Some(ass) = &exp.assignment
+                        && has_command
+                    {
+                        prefixed.save(&ass.key);
+                    }
+                    if let Some(ref mut ass) = exp.assignment
+                        && ass.eval(&self.state.clone()) != 0
+                    {
+                        // a failed assignment means the command doesn't run at all
+                        self.state.borrow().check_expansion()?;
+                        jobs.push(SlushJob::new(SlushJobType::Exited(1), None, None));
+                        continue;
                     }
 
                     if let Argument::Name(arg) = &exp.command
                         && arg.is_empty()
                     {
+                        // x=$(cmd) exits with cmd's status
+                        let state = self.state.borrow();
+                        if state.substituted {
+                            let status = SlushJobType::Exited(state.prev_status);
+                            jobs.push(SlushJob::new(status, None, None));
+                        }
                         continue;
                     }
 
-                    let base_command = exp.command.eval(&self.state.clone());
-                    let mut input_pipe = None;
-                    if i > 0
-                        && let Some(pipeline) = pipes.get_mut(i)
+                    let mut base_command = exp.command.eval(&self.state.clone());
+                    self.state.borrow().check_expansion()?;
+                    if SPECIAL_BUILTINS.contains(&base_command.as_str())
+                        && let Some(ass) = &exp.assignment
                     {
-                        input_pipe = Some(
-                            pipeline
-                                .borrow_mut()
-                                .0
-                                .try_clone()
-                                .expect("Error cloning pipe"),
-                        );
+                        prefixed.forget(&ass.key);
                     }
-                    let output_pipe: Option<PipeWriter>;
-                    if let Some(pipeline) = pipes.get_mut(i + 1) {
-                        output_pipe = Some(
-                            pipeline
-                                .borrow_mut()
-                                .1
-                                .try_clone()
-                                .expect("Error cloning pipe"),
-                        );
-                    } else if self.capture_out.is_some() {
-                        output_pipe = match pipe() {
-                            Ok((_, out)) => Some(out),
-                            Err(e) => return Err(format!("Error creating pipe: {e}")),
-                        }
+                    // `command name args` runs name skipping any function by that name
+                    let mut skip_functions = false;
+                    let mut default_path = false;
+                    let stripped;
+                    let exp: &CommandExpr = if base_command == "command"
+                        && let Some((use_default, command, arguments)) =
+                            lookup::strip_command(&exp.arguments)
+                    {
+                        skip_functions = true;
+                        default_path = use_default;
+                        base_command = command.eval(&self.state);
+                        stripped = CommandExpr {
+                            command,
+                            arguments: Rc::new(arguments),
+                            assignment: None,
+                            redirects: exp.redirects.clone(),
+                        };
+                        &stripped
                     } else {
-                        let p = io::stdout().as_raw_fd();
-                        unsafe {
-                            let dup_fd = libc::dup(p);
-                            output_pipe = Some(PipeWriter::from(OwnedFd::from_raw_fd(dup_fd)));
-                        }
-                    }
-
-                    if let Some(command) = self.state.borrow().built_ins.get(&base_command) {
-                        jobs.push(SlushJob::new(
-                            SlushJobType::Builtin(command.clone(), exp.arguments.clone()),
-                            input_pipe,
-                            output_pipe,
-                        ));
-                        continue;
-                    }
+                        exp
+                    };
 
-                    if self.state.borrow().functions.contains_key(&base_command) {
-                        jobs.push(SlushJob::new(
-                            SlushJobType::Function(base_command, exp.arguments.clone()),
-                            None,
-                            None,
-                        ));
+                    // Builtins and functions run inside the shell. The last stage runs
+                    // in the shell itself, so `echo x | read v` sets v, the others
+                    // run in a forked copy of it.
+                    let in_shell = if !skip_functions
+                        && self.state.borrow().functions.contains_key(&base_command)
+                    {
+                        // functions can shadow builtins
+                        Some(SlushJobType::Function(
+                            base_command.clone(),
+                            exp.arguments.clone(),
+                            exp.redirects.clone(),
+                        ))
+                    } else {
+                        self.state
+                            .borrow()
+                            .built_ins
+                            .get(&base_command)
+                            .map(|command| {
+                                SlushJobType::Builtin(
+                                    command.clone(),
+                                    exp.arguments.clone(),
+                                    exp.redirects.clone(),
+                                )
+                            })
+                    };
+                    if let Some(jobtype) = in_shell {
+                        let job = SlushJob::new(jobtype, input_pipe, output_pipe);
+                        jobs.push(if i + 1 < sz {
+                            job.fork(&pipes, &self.state)?
+                        } else {
+                            job
+                        });
                         continue;
                     }
 
-                    let cmd_str = exp.build_command_str(&self.state.clone());
-                    let mut cmd = cmd_str.build_command();
-
-                    let mut state = self.state.borrow_mut();
-
-                    if (input_pipe.is_some()
-                        || (self.file_redirect.is_some()
-                            && self.file_redirect.as_ref().unwrap().mode == RedirectType::In))
-                        && let Some(input) = &input_pipe
+                    let program = if default_path {
+                        lookup::find_in_path(&base_command, lookup::DEFAULT_PATH)
+                    } else {
+                        let path = env::var("PATH").unwrap_or_default();
+                        self.state
+                            .borrow_mut()
+                            .path_cache
+                            .find(&base_command, &path)
+                    };
+                    let program = match program {
+                        Ok(program) => program,
+                        Err(e) => {
+                            // the message goes wherever the command's stderr would have
+                            let actions = redirect::resolve(&exp.redirects, &self.state)?;
+                            let _saved = redirect::SavedFds::apply(&actions)?;
+                            eprintln!("{}", e.message(&base_command));
+                            jobs.push(SlushJob::new(SlushJobType::Exited(e.status()), None, None));
+                            continue;
+                        }
+                    };
+                    let mut cmd_str = exp.build_command_str(&self.state.clone());
+                    self.state.borrow().check_expansion()?;
+                    prompt::trace(&cmd_str.parts, &self.state);
+                    cmd_str.program = Some(program);
+                    let cmd = cmd_str.build_command();
+
+                    // shared with pre_exec, and kept to put a failure's message
+                    // where the command's stderr would have gone
+                    let actions = Arc::new(redirect::resolve(&exp.redirects, &self.state)?);
+                    let prepare = |mut cmd: Box<Command>| {
+                        if let Some(input) = &input_pipe {
+                            cmd.stdin(input.try_clone().unwrap());
+                        }
+                        if let Some(out) = &output_pipe {
+                            cmd.stdout(out.try_clone().unwrap());
+                        }
+                        // redirects go on last so they win over the pipes set up above
+                        if !actions.is_empty() {
+                            let actions = actions.clone();
+                            unsafe {
+                                cmd.pre_exec(move || redirect::apply_in_child(&actions));
+                            }
+                        }
+                        cmd
+                    };
+                    let mut spawned = prepare(cmd).spawn();
+                    // a file without #! is a script for the shell, as dash and bash run it
+                    if let Err(e) = &spawned
+                        && e.raw_os_error() == Some(libc::ENOEXEC)
+                        && let (Ok(shell), Some(program)) = (env::current_exe(), &cmd_str.program)
                     {
-                        cmd.stdin(input.try_clone().unwrap());
-                    }
-
-                    if let Some(out) = &output_pipe {
-                        cmd.stdout(out.try_clone().unwrap());
+                        let mut cmd = Box::new(Command::new(shell));
+                        cmd.arg0(cmd_str.name())
+                            .arg(program)
+                            .args(&cmd_str.parts[1..]);
+                        spawned = prepare(cmd).spawn();
                     }
-                    // we reset this above when we're capturing output... it wasn't
-                    // playing nice w/ the pipe systems
-                    if i == sz - 1 && self.capture_out.is_some() {
-                        cmd.stdout(Stdio::piped());
-                    }
-                    jobs.push(match cmd.spawn() {
+                    jobs.push(match spawned {
                         Ok(c) => match SharedChild::new(c) {
                             Ok(sc) => SlushJob::new(SlushJobType::Child(Arc::new(sc)), None, None),
                             Err(v) => {
                                 return Err(format!(
                                     "Error creating shared child {}: {}",
-                                    exp.command.eval(&self.state),
+                                    cmd_str.name(),
                                     v
                                 ));
                             }
                         },
-                        Err(v) => {
-                            return Err(format!(
-                                "Error spawning {}: {}",
-                                exp.command.eval(&self.state),
-                                v
-                            ));
+                        Err(e) => {
+                            let (message, status) = lookup::spawn_error(cmd_str.name(), &e);
+                            let _saved = redirect::SavedFds::apply(&actions)?;
+                            eprintln!("{message}");
+                            jobs.push(SlushJob::new(SlushJobType::Exited(status), None, None));
+                            continue;
                         }
                     });
 
@@ -733,13 +2162,26 @@ impl PipeLineExpr {
                             cmd: cmd_str,
                         };
 
+                        let mut state = self.state.borrow_mut();
                         if self.background {
                             state.bg_jobs.push(job);
                         } else {
                             state.fg_jobs.push(job);
                         }
                     }
-                    0
+                }
+                // if, while, for and { } run in the shell like builtins do
+                compound => {
+                    let job = SlushJob::new(
+                        SlushJobType::Compound(compound.clone()),
+                        input_pipe,
+                        output_pipe,
+                    );
+                    jobs.push(if i + 1 < sz {
+                        job.fork(&pipes, &self.state)?
+                    } else {
+                        job
+                    });
                 }
             };
         }
@@ -747,84 +2189,13 @@ impl PipeLineExpr {
     }
 
     fn eval(&mut self) -> Result<i32, String> {
-        let jobs = self.assemble_pipeline()?;
+        self.state.borrow_mut().lineno = self.line;
+        // put back once everything in the pipeline is done with them
+        let mut prefixed = SavedVariables::default();
+        let jobs = self.assemble_pipeline(&mut prefixed)?;
 
-        let mut prev_child: Option<Arc<SharedChild>> = None;
-        if let Some(job) = jobs.last()
-            && let SlushJobType::Child(child) = &job.jobtype
-        {
-            prev_child = Some(child.clone());
-        }
         let mut exit_status: i32 = 0;
-        if let Some(rcstr) = &self.capture_out {
-            if !self.background {
-                let outie = wait_with_output(&prev_child.unwrap());
-                rcstr
-                    .borrow_mut()
-                    .push_str(&String::from_utf8(outie.stdout.clone()).unwrap());
-                if rcstr.borrow().ends_with('\n') {
-                    rcstr.borrow_mut().pop();
-                }
-                exit_status = outie.status.expect("Couldn't get exit code from prev job");
-            } else {
-                exit_status = 0;
-            }
-        } else if self.file_redirect.is_some() {
-            let file_redirect = self.file_redirect.as_ref().unwrap();
-
-            let filename = file_redirect.file.eval(&self.state);
-            let mode = &file_redirect.mode;
-            // will these error silently?
-            let mut file = if *mode == RedirectType::OutAppend {
-                match File::options().append(true).open(filename) {
-                    Ok(f) => f,
-                    Err(_) => return Ok(1),
-                }
-            } else if *mode == RedirectType::Out {
-                match File::create(filename) {
-                    Ok(f) => f,
-                    Err(_) => return Ok(1),
-                }
-            } else if *mode == RedirectType::In {
-                match File::open(filename) {
-                    Ok(f) => f,
-                    Err(_) => return Ok(1),
-                }
-            } else {
-                panic!("Unexpected for redirect! Error Error!");
-            };
-            if *mode == RedirectType::In {
-                let mut buffer: Box<[u8]> = Box::new([0; 4096]);
-                let mut stdin = match prev_child.clone().unwrap().take_stdin() {
-                    Some(s) => s,
-                    None => {
-                        panic!("couldn't acquire stdin of process...");
-                    }
-                };
-
-                loop {
-                    let n = match file.read(&mut buffer) {
-                        Ok(n) => n,
-                        Err(e) => {
-                            println!("{e:?}");
-                            return Ok(1);
-                        }
-                    };
-                    if n == 0 {
-                        break;
-                    }
-                    if let Err(e) = stdin.write(&buffer[..n]) {
-                        panic!("Error writing to stdin: {e}");
-                    }
-                }
-                drop(stdin);
-                let status = prev_child.unwrap().wait().unwrap();
-                exit_status = status.code().unwrap_or(130);
-            } else {
-                let outie = wait_with_output(&prev_child.unwrap());
-                let _ = file.write_all(&outie.stdout.clone());
-            }
-        } else if let Some(job) = jobs.last() {
+        if let Some(job) = jobs.last() {
             match &job.jobtype {
                 SlushJobType::Child(child) => {
                     if !self.background {
@@ -834,41 +2205,23 @@ impl PipeLineExpr {
                         exit_status = 0;
                     }
                 }
-                SlushJobType::Builtin(builtin, args) => {
-                    let mut boxed_reader: Option<Box<dyn Read>> = job
-                        .stdin
-                        .as_ref()
-                        .map(|reader| Box::new(reader.try_clone().unwrap()) as Box<dyn Read>);
-                    let mut boxed_writer: Option<Box<dyn Write>> = job
-                        .stdout
-                        .as_ref()
-                        .map(|writer| Box::new(writer.try_clone().unwrap()) as Box<dyn Write>);
-                    exit_status = (builtin.command)(
-                        args,
-                        self.state.clone(),
-                        &mut boxed_reader,
-                        &mut boxed_writer,
-                    );
-                }
-                SlushJobType::Function(function, args) => {
-                    let argstack = self.state.borrow().argstack.clone();
-                    let aa = args
-                        .iter()
-                        .map(|a| -> Argument { Argument::Name(a.eval(&self.state)) })
-                        .collect();
-                    argstack.borrow_mut().push(Rc::new(aa));
-                    let mut functions = self.state.borrow_mut().functions.clone();
-                    let pl = functions.get_mut(function).unwrap().clone();
-                    {
-                        let mut ppl = pl.borrow_mut();
-                        for pipeline in ppl.iter_mut() {
-                            exit_status = pipeline.eval()?;
-                        }
-                    }
+                SlushJobType::Forked(_) => exit_status = job.wait(),
+                _ => exit_status = job.run_in_shell(&self.state)?,
+            }
+        }
 
-                    let argstack = &mut self.state.borrow_mut().argstack;
-                    argstack.borrow_mut().pop();
-                }
+        // The last stage is done, now wait for the rest. Our ends of the pipes
+        // are closed first so anything still writing to a finished reader
+        // gets SIGPIPE instead of blocking forever.
+        let rest: Vec<SlushJobType> = jobs
+            .into_iter()
+            .rev()
+            .skip(1)
+            .map(|job| job.jobtype)
+            .collect();
+        if !self.background {
+            for jobtype in rest {
+                SlushJob::new(jobtype, None, None).wait();
             }
         }
 
@@ -876,12 +2229,6 @@ impl PipeLineExpr {
     }
 }
 
-impl PipeLineExpr {
-    pub fn set_output_capture(&mut self, capture: Rc<RefCell<String>>) {
-        self.capture_out = Some(capture);
-    }
-}
-
 #[derive(Debug, PartialEq, Clone)]
 pub struct MergeExpr {
     pub left: Box<Argument>,
@@ -898,42 +2245,148 @@ impl MergeExpr {
 pub enum ExpansionExpr {
     ParameterExpansion(String), // the same as Argument::Variable
     StringLengthExpansion(String),
-    ParameterSubstitute(String, String), // if null or unset sets to default
-    ParameterAssign(String, String),     // if null or unset sets to default
-    ParameterError(String, String),      // if null sets null
+    // The forms with a word. The flag is set for the colon forms, like
+    // ${name:-word}, where a null value counts as unset.
+    ParameterSubstitute(String, Box<Argument>, bool), // ${name-word}
+    ParameterAssign(String, Box<Argument>, bool),     // ${name=word}
+    ParameterError(String, Box<Argument>, bool),      // ${name?word}
+    ParameterAlternate(String, Box<Argument>, bool),  // ${name+word}
+    RemoveShortestPrefix(String, PatternExpr),        // ${name#pattern}
+    RemoveLongestPrefix(String, PatternExpr),         // ${name##pattern}
+    RemoveShortestSuffix(String, PatternExpr),        // ${name%pattern}
+    RemoveLongestSuffix(String, PatternExpr),         // ${name%%pattern}
+    // bash extensions, only available after `set -o extensions`
+    Substring(String, Box<Argument>), // ${name:offset:length}, the word is "offset:length"
+    Replace(String, PatternExpr, Box<Argument>, pattern::ReplaceMode), // ${name/pattern/string}
+    ChangeCase(String, PatternExpr, pattern::CaseChange), // ${name^^pattern}
+}
+
+// The pattern in an expansion like ${name%.txt}. Each part is expanded on
+// its own, quoted parts only match themselves.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PatternExpr {
+    pub parts: Vec<(Argument, bool)>, // true when quoted
+}
+
+impl PatternExpr {
+    fn eval(&self, state: &Rc<RefCell<State>>) -> String {
+        self.parts
+            .iter()
+            .map(|(part, quoted)| {
+                let value = part.eval(state);
+                if *quoted {
+                    pattern::escape(&value)
+                } else {
+                    value
+                }
+            })
+            .collect()
+    }
 }
 
 impl ExpansionExpr {
     fn eval(&self, state: &Rc<RefCell<State>>) -> String {
+        let extension = matches!(
+            self,
+            ExpansionExpr::Substring(..)
+                | ExpansionExpr::Replace(..)
+                | ExpansionExpr::ChangeCase(..)
+        );
+        if extension && !state.borrow().options.is_set("extensions") {
+            eprintln!("slush: bad substitution, this needs `set -o extensions`");
+            state.borrow_mut().expansion_failed = true;
+            return String::new();
+        }
         match self {
             ExpansionExpr::ParameterExpansion(var) => {
                 get_variable(var.clone(), state).unwrap_or_default()
             }
+            // characters, not bytes
             ExpansionExpr::StringLengthExpansion(var) => get_variable(var.clone(), state)
                 .unwrap_or_default()
-                .len()
+                .chars()
+                .count()
                 .to_string(),
-            ExpansionExpr::ParameterSubstitute(var, default) => {
-                if let Some(v) = get_variable(var.clone(), state) {
-                    v
+            ExpansionExpr::ParameterSubstitute(var, default, colon) => {
+                match set_value(var, *colon, state) {
+                    Some(v) => v,
+                    None => default.eval(state),
+                }
+            }
+            ExpansionExpr::ParameterError(var, err, colon) => {
+                if let Some(v) = set_value(var, *colon, state) {
+                    return v;
+                }
+                let err = err.eval(state);
+                if err.is_empty() {
+                    eprintln!("slush: {var}: parameter not set");
                 } else {
-                    default.clone()
+                    eprintln!("slush: {var}: {err}");
+                }
+                state.borrow_mut().expansion_failed = true;
+                String::new()
+            }
+            ExpansionExpr::ParameterAssign(var, default, colon) => {
+                match set_value(var, *colon, state) {
+                    Some(v) => v,
+                    None => {
+                        let default = default.eval(state);
+                        if let Err(e) = state.borrow().set_variable(var, &default) {
+                            eprintln!("slush: {e}");
+                        }
+                        default
+                    }
                 }
             }
-            ExpansionExpr::ParameterError(var, err) => {
-                eprintln!("slush: {var}: {err}");
-                std::process::exit(1);
+            ExpansionExpr::ParameterAlternate(var, alternate, colon) => {
+                match set_value(var, *colon, state) {
+                    Some(_) => alternate.eval(state),
+                    None => String::new(),
+                }
             }
-            ExpansionExpr::ParameterAssign(var, default) => {
-                if let Some(v) = get_variable(var.clone(), state) {
-                    v
-                } else {
-                    unsafe {
-                        env::set_var(var, default);
+            ExpansionExpr::RemoveShortestPrefix(var, pat) => pattern::remove_prefix(
+                &get_variable(var.clone(), state).unwrap_or_default(),
+                &pat.eval(state),
+                false,
+            ),
+            ExpansionExpr::RemoveLongestPrefix(var, pat) => pattern::remove_prefix(
+                &get_variable(var.clone(), state).unwrap_or_default(),
+                &pat.eval(state),
+                true,
+            ),
+            ExpansionExpr::RemoveShortestSuffix(var, pat) => pattern::remove_suffix(
+                &get_variable(var.clone(), state).unwrap_or_default(),
+                &pat.eval(state),
+                false,
+            ),
+            ExpansionExpr::RemoveLongestSuffix(var, pat) => pattern::remove_suffix(
+                &get_variable(var.clone(), state).unwrap_or_default(),
+                &pat.eval(state),
+                true,
+            ),
+            ExpansionExpr::Substring(var, spec) => {
+                let value = get_variable(var.clone(), state).unwrap_or_default();
+                let spec = spec.eval(state);
+                match substring(&value, &spec) {
+                    Some(part) => part,
+                    None => {
+                        eprintln!("slush: {spec}: bad substring");
+                        state.borrow_mut().expansion_failed = true;
+                        String::new()
                     }
-                    default.clone()
                 }
             }
+            ExpansionExpr::Replace(var, pat, with, mode) => pattern::replace(
+                &get_variable(var.clone(), state).unwrap_or_default(),
+                &pat.eval(state),
+                &with.eval(state),
+                *mode,
+            ),
+            ExpansionExpr::ChangeCase(var, pat, change) => pattern::change_case(
+                &get_variable(var.clone(), state).unwrap_or_default(),
+                &pat.eval(state),
+                *change,
+            ),
         }
     }
 }
@@ -955,12 +2408,59 @@ impl Argument {
             Argument::Variable(variable) => {
                 get_variable(variable.name.clone(), state).unwrap_or_default()
             }
-            Argument::SubShell(ss) => ss.stdout(),
+            Argument::SubShell(ss) => ss.stdout(state),
             Argument::Merge(merge) => merge.eval(state),
             Argument::Expansion(expansion) => expansion.eval(state),
             Argument::QuoteString(string) => evaluate_string(string, state).unwrap_or_default(),
         }
     }
+
+    // The words an argument turns into on a command line. Only $@ makes
+    // more than one, a word per positional parameter even inside double
+    // quotes, and "$@" with no parameters makes none at all.
+    pub fn eval_fields(&self, state: &Rc<RefCell<State>>) -> Vec<String> {
+        match self {
+            Argument::Variable(variable) if variable.name == "@" => positional_fields(state),
+            Argument::QuoteString(string) => quoted_fields(string, state),
+            Argument::Merge(merge) => join_fields(
+                merge.left.eval_fields(state),
+                merge.right.eval_fields(state),
+            ),
+            _ => vec![self.eval(state)],
+        }
+    }
+}
+
+fn positional_fields(state: &Rc<RefCell<State>>) -> Vec<String> {
+    let positionals = state.borrow().positionals();
+    positionals.iter().map(|arg| arg.eval(state)).collect()
+}
+
+// Words written next to each other, the last of the left ones and the
+// first of the right ones become one. "pre$@post" is pre joined to the
+// first parameter and the last one joined to post.
+fn join_fields(mut left: Vec<String>, right: Vec<String>) -> Vec<String> {
+    match (left.last_mut(), right.split_first()) {
+        (Some(last), Some((first, rest))) => {
+            last.push_str(first);
+            left.extend_from_slice(rest);
+            left
+        }
+        (None, _) => right,
+        (_, None) => left,
+    }
+}
+
+fn quoted_fields(string: &str, state: &Rc<RefCell<State>>) -> Vec<String> {
+    let mut parser = Parser::new(state.clone());
+    match parser.parse_double_quoted_string(string) {
+        Ok(args) if !args.is_empty() => args
+            .iter()
+            .map(|arg| arg.eval_fields(state))
+            .reduce(join_fields)
+            .unwrap_or_default(),
+        _ => vec![evaluate_string(string, state).unwrap_or_default()],
+    }
 }
 
 fn evaluate_string(string: &str, state: &Rc<RefCell<State>>) -> Option<String> {
@@ -970,7 +2470,7 @@ fn evaluate_string(string: &str, state: &Rc<RefCell<State>>) -> Option<String> {
             .into_iter()
             .map(|a| a.eval(state))
             .reduce(|whole, next| whole + &next)
-            .unwrap(),
+            .unwrap_or_default(),
         Err(e) => {
             println!("Slush Error {e}");
             String::default()
@@ -979,10 +2479,57 @@ fn evaluate_string(string: &str, state: &Rc<RefCell<State>>) -> Option<String> {
     Some(ret)
 }
 
+// ${name:offset:length} in chars. A negative offset counts from the end, a
+// negative length leaves that many chars off the end. None when the numbers
+// don't make sense.
+fn substring(value: &str, spec: &str) -> Option<String> {
+    let number = |word: &str| -> Option<i64> {
+        let word = word.trim();
+        let word = word
+            .strip_prefix('(')
+            .and_then(|word| word.strip_suffix(')'))
+            .unwrap_or(word)
+            .trim();
+        if word.is_empty() {
+            return Some(0);
+        }
+        word.parse().ok()
+    };
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len() as i64;
+    let (offset, length) = match spec.split_once(':') {
+        Some((offset, length)) => (offset, Some(length)),
+        None => (spec, None),
+    };
+    let mut start = number(offset)?;
+    if start < 0 {
+        start += len;
+    }
+    if start < 0 || start > len {
+        return Some(String::new());
+    }
+    let end = match length {
+        Some(length) => match number(length)? {
+            length if length < 0 => len + length,
+            length => (start + length).min(len),
+        },
+        None => len,
+    };
+    if end < start {
+        return None;
+    }
+    Some(chars[start as usize..end as usize].iter().collect())
+}
+
+// the value of var if it is set, with colon a null value counts as unset
+fn set_value(var: &str, colon: bool, state: &Rc<RefCell<State>>) -> Option<String> {
+    get_variable(var.to_string(), state).filter(|value| !colon || !value.is_empty())
+}
+
 fn get_variable(var: String, state: &Rc<RefCell<State>>) -> Option<String> {
     let s = state.borrow();
     match var.as_str() {
-        "0" => Some(String::from("slush")),
+        "0" => Some(s.shell_name.clone()),
         "!" => Some(if let Some(job) = s.bg_jobs.last() {
             job.child.id().to_string()
         } else {
@@ -990,6 +2537,10 @@ fn get_variable(var: String, state: &Rc<RefCell<State>>) -> Option<String> {
         }),
         "?" => Some(state.borrow().prev_status.to_string()),
         "$" => Some(process::id().to_string()),
+        "LINENO" => Some(s.lineno.to_string()),
+        "PPID" => Some(s.ppid.to_string()),
+        "RANDOM" => Some(s.random.next().to_string()),
+        "SECONDS" => Some(s.seconds.get().to_string()),
         "@" | "*" => {
             // I don't think '*' is the 'spec complient'
             let argstack = s.argstack.borrow();
@@ -1003,87 +2554,35 @@ fn get_variable(var: String, state: &Rc<RefCell<State>>) -> Option<String> {
                     .iter()
                     .map(|arg| arg.eval(state))
                     .reduce(|whole, new| whole.to_owned() + " " + &new)
-                    .unwrap()
-                    .to_string(),
+                    .unwrap_or_default(),
             )
         }
-        "#" => {
-            let argstack = s.argstack.borrow();
-            if argstack.is_empty() {
-                return Some(String::default());
-            }
-            Some(format!("{}", argstack.last().unwrap().len()))
-        }
+        "#" => Some(s.positionals().len().to_string()),
         "-" => {
             panic!("'{var}' parameters are not yet supported")
         }
         _ => {
             if let Ok(number) = var.parse::<usize>() {
-                let argstack = s.argstack.clone();
-
-                let args = argstack.borrow();
-                args.last().map(|a| {
-                    a.get(number - 1)
-                        .unwrap_or(&Argument::Name("".to_string()))
-                        .eval(state)
-                })
+                if number == 0 {
+                    return Some(s.shell_name.clone());
+                }
+                s.positionals().get(number - 1).map(|arg| arg.eval(state))
             } else {
-                let text = env::var(var).unwrap_or_default();
-                if text.is_empty() { None } else { Some(text) }
+                env::var(var).ok()
             }
         }
     }
 }
 
-fn wait_with_output(child: &SharedChild) -> Output {
-    drop(child.take_stdin());
-    let cid = child.id();
-
-    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
-    match (child.take_stdout(), child.take_stderr()) {
-        (None, None) => {}
-        (Some(mut out), None) => {
-            out.read_to_end(&mut stdout)
-                .unwrap_or_else(|_| panic!("Error reading stdout from pid {cid}"));
-        }
-        (None, Some(mut err)) => {
-            err.read_to_end(&mut stderr)
-                .unwrap_or_else(|_| panic!("Error reading from stderr from pid {cid}"));
-        }
-        (Some(mut out), Some(mut err)) => {
-            let out_handle = std::thread::spawn(move || {
-                out.read_to_end(&mut stdout)
-                    .unwrap_or_else(|_| panic!("Error reading stdout from pid {cid}"));
-                stdout
-            });
-            let err_handle = std::thread::spawn(move || {
-                err.read_to_end(&mut stderr)
-                    .unwrap_or_else(|_| panic!("Error reading from stderr from pid {cid}"));
-                stderr
-            });
-
-            stdout = out_handle.join().expect("thread panicked");
-            stderr = err_handle.join().expect("thread panicked");
-        }
-    }
-
-    let status = child
-        .wait()
-        .unwrap_or_else(|_| panic!("Error waiting from pid {cid}"));
-    Output {
-        status: status.code(),
-        stdout,
-        _stderr: stderr,
+// set -x for a builtin or function, its words are already expanded
+fn trace_call(name: &str, args: &[Argument], state: &Rc<RefCell<State>>) {
+    if state.borrow().options.is_set("xtrace") {
+        let mut words = vec![name.to_string()];
+        words.extend(args.iter().map(|arg| arg.eval(state)));
+        prompt::trace(&words, state);
     }
 }
 
-#[derive(Clone)]
-pub struct Output {
-    status: Option<i32>,
-    stdout: Vec<u8>,
-    _stderr: Vec<u8>,
-}
-
 fn handle_jobs_cmd(opt: Option<&str>, state: &Rc<RefCell<State>>) {
     match opt {
         None => {
@@ -1145,3 +2644,105 @@ impl Job {
         }
     }
 }
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::{Argument, ExpansionExpr, State, SubShellExpr, substring};
+    #[allow(unused_imports)]
+    use std::env;
+
+    #[test]
+    fn test_readonly_variables() {
+        let state = State::new();
+        state
+            .borrow()
+            .set_variable("SLUSH_TEST_READONLY", "fixed")
+            .unwrap();
+        state
+            .borrow_mut()
+            .readonly
+            .insert("SLUSH_TEST_READONLY".to_string());
+        assert!(
+            state
+                .borrow()
+                .set_variable("SLUSH_TEST_READONLY", "changed")
+                .is_err()
+        );
+        assert!(
+            state
+                .borrow()
+                .unset_variable("SLUSH_TEST_READONLY")
+                .is_err()
+        );
+        assert_eq!(env::var("SLUSH_TEST_READONLY").unwrap(), "fixed");
+    }
+
+    #[test]
+    fn test_unset_is_not_empty() {
+        let state = State::new();
+        state.borrow().set_variable("SLUSH_TEST_EMPTY", "").unwrap();
+        assert_eq!(env::var("SLUSH_TEST_EMPTY").ok(), Some(String::new()));
+        state.borrow().unset_variable("SLUSH_TEST_EMPTY").unwrap();
+        assert_eq!(env::var("SLUSH_TEST_EMPTY").ok(), None);
+        assert!(state.borrow().set_variable("BAD=NAME", "x").is_err());
+    }
+
+    #[test]
+    fn test_substring() {
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(substring("hello world", "1:3"), some("ell"));
+        assert_eq!(substring("hello world", " -5"), some("world"));
+        assert_eq!(substring("hello world", "(-5):2"), some("wo"));
+        assert_eq!(substring("hello world", "2:-2"), some("llo wor"));
+        assert_eq!(substring("hello world", ":2"), some("he"));
+        assert_eq!(substring("héllo", "1:1"), some("é"));
+        assert_eq!(substring("hello", "9"), some(""));
+        assert_eq!(substring("hello", "3:-4"), None);
+        assert_eq!(substring("hello", "x"), None);
+    }
+
+    #[test]
+    fn test_quoted_at_fields() {
+        let state = State::new();
+        let name = |n: &str| Argument::Name(n.to_string());
+        state
+            .borrow_mut()
+            .set_positionals(vec![name("printf"), name("[%s]\\n"), name("a b")]);
+        let fields = |word: &str| Argument::QuoteString(word.to_string()).eval_fields(&state);
+        assert_eq!(fields("$@"), ["printf", "[%s]\\n", "a b"]);
+        assert_eq!(fields("<$@>"), ["<printf", "[%s]\\n", "a b>"]);
+        assert_eq!(fields("$*"), ["printf [%s]\\n a b"]);
+        state.borrow_mut().set_positionals(Vec::new());
+        assert!(fields("$@").is_empty());
+        assert_eq!(fields("<$@>"), ["<>"]);
+        assert_eq!(fields(""), [""]);
+    }
+
+    #[test]
+    fn test_substitution_status() {
+        let state = State::new();
+        state.borrow_mut().lineno = 1;
+        let substitute = |shell: &str| {
+            SubShellExpr {
+                shell: shell.to_string(),
+            }
+            .stdout(&state)
+        };
+        assert_eq!(substitute("printf 'a\\377b\\n\\n'; exit 3"), "a\u{FFFD}b");
+        assert_eq!(state.borrow().prev_status, 3);
+        assert!(state.borrow().substituted);
+        assert_eq!(substitute("false"), "");
+        assert_eq!(state.borrow().prev_status, 1);
+    }
+
+    #[test]
+    fn test_length_in_chars() {
+        let state = State::new();
+        state
+            .borrow()
+            .set_variable("SLUSH_TEST_LENGTH", "h\u{e9}llo \u{FFFD}")
+            .unwrap();
+        let length = ExpansionExpr::StringLengthExpansion("SLUSH_TEST_LENGTH".to_string());
+        assert_eq!(Argument::Expansion(length).eval(&state), "7");
+    }
+}
@@ -1,5 +1,10 @@
+use crate::expr::State;
+use std::cell::RefCell;
 use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
+use std::rc::Rc;
 
 fn normalize_path(path: &str) -> String {
     // this feels a bit untidy if im being honest... far too much state
@@ -12,7 +17,7 @@ fn normalize_path(path: &str) -> String {
         } else {
             if current == ".." {
                 path_stack.pop();
-            } else if !current.is_empty() {
+            } else if !current.is_empty() && current != "." {
                 path_stack.push(current.clone());
             }
             current.clear();
@@ -21,45 +26,138 @@ fn normalize_path(path: &str) -> String {
     }
     if current == ".." {
         path_stack.pop();
-    } else if !current.is_empty() {
+    } else if !current.is_empty() && current != "." {
         path_stack.push(current.clone());
     }
     String::from("/") + &path_stack.join("/")
 }
 
+// The directory the shell starts in. $PWD is kept if it really names the
+// current directory, otherwise we fall back to the physical path.
+pub fn initial_cwd() -> String {
+    let physical = env::current_dir()
+        .map(|dir| dir.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match env::var("PWD") {
+        Ok(pwd) if normalize_path(&pwd) == pwd && same_file(&pwd, &physical) => pwd,
+        _ => physical,
+    }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+// Relative directories are searched for in each CDPATH entry. Returns the
+// directory when it was found through a non-empty entry, an empty entry
+// means the current directory and the plain operand is used as is.
+fn search_cdpath(dir: &str, cdpath: &str) -> Option<String> {
+    let first = dir.split('/').next().unwrap_or("");
+    if cdpath.is_empty() || dir.starts_with('/') || first == "." || first == ".." {
+        return None;
+    }
+    for entry in cdpath.split(':') {
+        if entry.is_empty() {
+            if Path::new(dir).is_dir() {
+                return None;
+            }
+            continue;
+        }
+        let candidate = format!("{}/{dir}", entry.trim_end_matches('/'));
+        if Path::new(&candidate).is_dir() {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
 pub struct ChangeDir {
-    path: Box<Path>,
+    dir: Option<String>,
+    physical: bool, // -P, resolve symlinks instead of tracking the path we took
 }
+
 impl ChangeDir {
-    pub fn eval(&self) -> i32 {
-        unsafe {
-            env::set_var(
-                "PWD",
-                normalize_path(&String::from(
-                    Path::new(&env::var("PWD").unwrap_or("".to_string()))
-                        .join(&self.path)
-                        .as_os_str()
-                        .to_str()
-                        .unwrap_or(""),
-                )),
-            );
-        }
-        match env::set_current_dir(&self.path) {
-            Ok(()) => 0,
-            Err(_) => 1,
+    // on success returns the new directory if it should be printed, which
+    // is the case for `cd -` and when the directory was found through CDPATH
+    pub fn eval(&self, state: &Rc<RefCell<State>>) -> Result<Option<String>, String> {
+        let mut print = false;
+        let dir = match self.dir.as_deref() {
+            None => env::var("HOME")
+                .ok()
+                .filter(|home| !home.is_empty())
+                .ok_or("cd: HOME not set")?,
+            Some("-") => {
+                print = true;
+                env::var("OLDPWD")
+                    .ok()
+                    .filter(|old| !old.is_empty())
+                    .ok_or("cd: OLDPWD not set")?
+            }
+            Some(dir) => match search_cdpath(dir, &env::var("CDPATH").unwrap_or_default()) {
+                Some(found) => {
+                    print = true;
+                    found
+                }
+                None => dir.to_string(),
+            },
+        };
+
+        let old = state.borrow().cwd.clone();
+        let new = if self.physical {
+            env::set_current_dir(&dir).map_err(|_| format!("cd: can't cd to {dir}"))?;
+            env::current_dir()
+                .map(|dir| dir.to_string_lossy().into_owned())
+                .map_err(|e| format!("cd: {e}"))?
+        } else {
+            let logical = if dir.starts_with('/') {
+                normalize_path(&dir)
+            } else {
+                normalize_path(&format!("{old}/{dir}"))
+            };
+            env::set_current_dir(&logical).map_err(|_| format!("cd: can't cd to {dir}"))?;
+            logical
+        };
+
+        {
+            let state = state.borrow();
+            state.set_variable("OLDPWD", &old)?;
+            state.set_variable("PWD", &new)?;
         }
+        state.borrow_mut().cwd = new.clone();
+        Ok(print.then_some(new))
     }
 
-    pub fn new(path: &str) -> ChangeDir {
-        ChangeDir {
-            path: Path::new(path).into(),
+    pub fn new(args: &[String]) -> Result<ChangeDir, String> {
+        let mut physical = false;
+        let mut rest = args;
+        while let Some(opt) = rest.first() {
+            match opt.as_str() {
+                "-L" => physical = false,
+                "-P" => physical = true,
+                "--" => {
+                    rest = &rest[1..];
+                    break;
+                }
+                _ => break,
+            }
+            rest = &rest[1..];
         }
+        if rest.len() > 1 {
+            return Err("cd: too many arguments".to_string());
+        }
+        Ok(ChangeDir {
+            dir: rest.first().cloned(),
+            physical,
+        })
     }
 }
 
 mod tests {
     #[allow(unused_imports)]
-    use crate::expr::change_dir::normalize_path;
+    use crate::expr::change_dir::{normalize_path, search_cdpath};
     #[test]
     fn test_normalize_paths() {
         let path = normalize_path(&String::from("/patha/../pathb"));
@@ -81,5 +179,21 @@ mod tests {
         let path = normalize_path(&String::from("/patha/space\\/path//../pathb"));
         println!("Got path: {}", path);
         assert!(path == "/patha/pathb");
+
+        let path = normalize_path(&String::from("/patha/./pathb/."));
+        assert!(path == "/patha/pathb");
+    }
+
+    #[test]
+    fn test_search_cdpath() {
+        assert_eq!(search_cdpath("bin", "/usr"), Some("/usr/bin".to_string()));
+        assert_eq!(
+            search_cdpath("bin", "/nonexistent:/usr/"),
+            Some("/usr/bin".to_string())
+        );
+        assert_eq!(search_cdpath("bin", ""), None);
+        assert_eq!(search_cdpath("/bin", "/usr"), None);
+        assert_eq!(search_cdpath("./bin", "/usr"), None);
+        assert_eq!(search_cdpath("nonexistent", "/usr"), None);
     }
 }
@@ -0,0 +1,149 @@
+// Where getopts left off. OPTIND only says which argument we are on, for
+// clustered options like '-vo file' we also need to remember how far into
+// the argument we are. If OPTIND is changed by the script (usually reset to
+// 1) the offset no longer makes sense and is thrown away.
+#[derive(Debug, Default, Clone)]
+pub struct GetoptsState {
+    optind: usize,
+    offset: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GetoptsResult {
+    Opt(char, Option<String>), // a valid option and its argument, if it takes one
+    Illegal(char),             // an option that isn't in optstring
+    MissingArg(char),          // an option that needs an argument but didn't get one
+    Done,                      // no more options
+}
+
+impl GetoptsState {
+    pub fn next(&mut self, optstring: &str, args: &[String], optind: usize) -> GetoptsResult {
+        if optind != self.optind {
+            self.offset = 0;
+        }
+        let (result, optind, offset) = getopts(optstring, args, optind.max(1), self.offset);
+        self.optind = optind;
+        self.offset = offset;
+        result
+    }
+
+    pub fn optind(&self) -> usize {
+        self.optind
+    }
+}
+
+// returns the result along with the new OPTIND and offset
+fn getopts(
+    optstring: &str,
+    args: &[String],
+    optind: usize,
+    offset: usize,
+) -> (GetoptsResult, usize, usize) {
+    let Some(arg) = args.get(optind - 1) else {
+        return (GetoptsResult::Done, optind, 0);
+    };
+    let mut offset = offset;
+    if offset == 0 {
+        if arg == "--" {
+            return (GetoptsResult::Done, optind + 1, 0);
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            return (GetoptsResult::Done, optind, 0);
+        }
+        offset = 1;
+    }
+
+    let chars: Vec<char> = arg.chars().collect();
+    let Some(&opt) = chars.get(offset) else {
+        return getopts(optstring, args, optind + 1, 0);
+    };
+    offset += 1;
+    let (optind, offset) = if offset >= chars.len() {
+        (optind + 1, 0)
+    } else {
+        (optind, offset)
+    };
+
+    let spec = optstring.strip_prefix(':').unwrap_or(optstring);
+    let Some(pos) = spec.find(opt).filter(|_| opt != ':') else {
+        return (GetoptsResult::Illegal(opt), optind, offset);
+    };
+    if !spec[pos + opt.len_utf8()..].starts_with(':') {
+        return (GetoptsResult::Opt(opt, None), optind, offset);
+    }
+
+    // the rest of a clustered argument is the option argument, '-ofile'
+    if offset != 0 {
+        let rest: String = chars[offset..].iter().collect();
+        return (GetoptsResult::Opt(opt, Some(rest)), optind + 1, 0);
+    }
+    match args.get(optind - 1) {
+        Some(next) => (GetoptsResult::Opt(opt, Some(next.clone())), optind + 1, 0),
+        None => (GetoptsResult::MissingArg(opt), optind, 0),
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::getopts::{GetoptsResult, GetoptsState};
+
+    #[allow(dead_code)]
+    fn run(optstring: &str, args: &[&str]) -> Vec<GetoptsResult> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let mut state = GetoptsState::default();
+        let mut results = Vec::new();
+        loop {
+            let optind = state.optind();
+            let result = state.next(optstring, &args, optind);
+            if result == GetoptsResult::Done {
+                break;
+            }
+            results.push(result);
+        }
+        results
+    }
+
+    #[test]
+    fn test_separate_options() {
+        let results = run("vo:", &["-v", "-o", "file", "rest"]);
+        assert_eq!(
+            results,
+            vec![
+                GetoptsResult::Opt('v', None),
+                GetoptsResult::Opt('o', Some("file".to_string()))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clustered_options() {
+        let results = run("vxo:", &["-vxo", "file", "-ofile2", "--", "-v"]);
+        assert_eq!(
+            results,
+            vec![
+                GetoptsResult::Opt('v', None),
+                GetoptsResult::Opt('x', None),
+                GetoptsResult::Opt('o', Some("file".to_string())),
+                GetoptsResult::Opt('o', Some("file2".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_options() {
+        let results = run(":vo:", &["-q", "-o"]);
+        assert_eq!(
+            results,
+            vec![GetoptsResult::Illegal('q'), GetoptsResult::MissingArg('o')]
+        );
+    }
+
+    #[test]
+    fn test_optind_after_options() {
+        let args: Vec<String> = ["-v", "file"].iter().map(|a| a.to_string()).collect();
+        let mut state = GetoptsState::default();
+        assert_eq!(state.next("v", &args, 1), GetoptsResult::Opt('v', None));
+        assert_eq!(state.next("v", &args, state.optind()), GetoptsResult::Done);
+        assert_eq!(state.optind(), 2);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::expr::{Argument, State};
+use crate::parser::tokenizer::is_keyword;
+use std::collections::BTreeMap;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+// used by `command -p`, a PATH that finds the standard utilities
+pub const DEFAULT_PATH: &str = "/usr/bin:/bin";
+
+// What a command name refers to, the variants are in the order the shell
+// looks them up.
+#[derive(Debug, PartialEq)]
+pub enum CommandType {
+    Keyword,
+    Alias(String),
+    Function,
+    Builtin,
+    File(PathBuf),
+}
+
+pub fn command_type(name: &str, state: &State, path: &str) -> Option<CommandType> {
+    if is_keyword(name) {
+        return Some(CommandType::Keyword);
+    }
+    if let Some(value) = state.aliases.get(name) {
+        return Some(CommandType::Alias(value.clone()));
+    }
+    if state.functions.contains_key(name) {
+        return Some(CommandType::Function);
+    }
+    if state.built_ins.contains_key(name) {
+        return Some(CommandType::Builtin);
+    }
+    find_in_path(name, path).ok().map(CommandType::File)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SearchError {
+    NotFound,
+    NotExecutable, // there is a file by that name but it can't be run
+}
+
+impl SearchError {
+    pub fn status(&self) -> i32 {
+        match self {
+            SearchError::NotFound => 127,
+            SearchError::NotExecutable => 126,
+        }
+    }
+
+    pub fn message(&self, name: &str) -> String {
+        match self {
+            SearchError::NotFound => format!("slush: {name}: not found"),
+            SearchError::NotExecutable => format!("slush: {name}: Permission denied"),
+        }
+    }
+}
+
+// What to say when a command that was found fails to start, and the status
+// that goes with it, 127 when something it needs like its #! interpreter is
+// missing and 126 for anything else.
+pub fn spawn_error(name: &str, err: &io::Error) -> (String, i32) {
+    match err.kind() {
+        io::ErrorKind::NotFound => (SearchError::NotFound.message(name), 127),
+        io::ErrorKind::PermissionDenied => (SearchError::NotExecutable.message(name), 126),
+        _ => (format!("slush: {name}: {err}"), 126),
+    }
+}
+
+// names with a slash in them aren't searched for, they are used as is
+pub fn find_in_path(name: &str, path: &str) -> Result<PathBuf, SearchError> {
+    if name.contains('/') {
+        let file = PathBuf::from(name);
+        return match is_executable(&file) {
+            Some(true) => Ok(file),
+            Some(false) => Err(SearchError::NotExecutable),
+            None => Err(SearchError::NotFound),
+        };
+    }
+    let mut err = SearchError::NotFound;
+    for dir in path.split(':') {
+        let dir = if dir.is_empty() { "." } else { dir };
+        let candidate = Path::new(dir).join(name);
+        match is_executable(&candidate) {
+            Some(true) => return Ok(candidate),
+            Some(false) if candidate.is_file() => err = SearchError::NotExecutable,
+            _ => {}
+        }
+    }
+    Err(err)
+}
+
+// None when there is nothing there at all
+pub fn is_executable(path: &Path) -> Option<bool> {
+    let meta = path.metadata().ok()?;
+    Some(meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+// Where commands were last found on PATH, so running the same command over
+// and over doesn't search every directory each time. The cache belongs to
+// one value of PATH and is thrown away once PATH is assigned something else.
+#[derive(Debug, Default)]
+pub struct PathCache {
+    path: String,
+    entries: BTreeMap<String, PathBuf>,
+}
+
+impl PathCache {
+    pub fn find(&mut self, name: &str, path: &str) -> Result<PathBuf, SearchError> {
+        if name.contains('/') {
+            return find_in_path(name, path);
+        }
+        if self.path != path {
+            self.path = path.to_string();
+            self.entries.clear();
+        }
+        // the file could have been removed since it was cached
+        if let Some(file) = self.entries.get(name)
+            && is_executable(file) == Some(true)
+        {
+            return Ok(file.clone());
+        }
+        let file = find_in_path(name, path)?;
+        self.entries.insert(name.to_string(), file.clone());
+        Ok(file)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &PathBuf> {
+        self.entries.values()
+    }
+}
+
+// the sentence `type` and `command -V` print
+pub fn describe(name: &str, kind: &CommandType) -> String {
+    match kind {
+        CommandType::Keyword => format!("{name} is a shell keyword"),
+        CommandType::Alias(value) => format!("{name} is an alias for {value}"),
+        CommandType::Function => format!("{name} is a shell function"),
+        CommandType::Builtin => format!("{name} is a shell builtin"),
+        CommandType::File(path) => format!("{name} is {}", path.display()),
+    }
+}
+
+// `command [-p] name args...` runs name without looking at functions. This
+// splits the options off so the pipeline can run name directly, returning
+// whether -p was given, the name and its arguments. The -v and -V queries
+// (and a bare `command`) are left for the builtin.
+pub fn strip_command(args: &[Argument]) -> Option<(bool, Argument, Vec<Argument>)> {
+    let mut default_path = false;
+    let mut rest = args;
+    while let Some(Argument::Name(opt)) = rest.first() {
+        if opt == "--" {
+            rest = &rest[1..];
+            break;
+        }
+        if opt == "-p" {
+            default_path = true;
+            rest = &rest[1..];
+            continue;
+        }
+        if opt.starts_with('-') && opt.len() > 1 {
+            return None;
+        }
+        break;
+    }
+    let (command, arguments) = rest.split_first()?;
+    Some((default_path, command.clone(), arguments.to_vec()))
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::lookup::{
+        CommandType, PathCache, SearchError, command_type, find_in_path, spawn_error, strip_command,
+    };
+    #[allow(unused_imports)]
+    use crate::expr::{Argument, State};
+    #[allow(unused_imports)]
+    use nix::libc;
+    #[allow(unused_imports)]
+    use std::io;
+
+    #[test]
+    fn test_command_type_order() {
+        let state = State::new();
+        state
+            .borrow_mut()
+            .aliases
+            .insert("cd".to_string(), "cd /".to_string());
+        let state = state.borrow();
+        assert_eq!(command_type("if", &state, ""), Some(CommandType::Keyword));
+        assert_eq!(
+            command_type("cd", &state, ""),
+            Some(CommandType::Alias("cd /".to_string()))
+        );
+        assert_eq!(command_type("exit", &state, ""), Some(CommandType::Builtin));
+        assert_eq!(command_type("sh", &state, ""), None);
+        assert_eq!(
+            command_type("sh", &state, "/nonexistent:/bin"),
+            Some(CommandType::File("/bin/sh".into()))
+        );
+    }
+
+    #[test]
+    fn test_find_in_path() {
+        assert_eq!(find_in_path("sh", "/bin"), Ok("/bin/sh".into()));
+        assert_eq!(find_in_path("/bin/sh", ""), Ok("/bin/sh".into()));
+        assert_eq!(
+            find_in_path("/etc/passwd", ""),
+            Err(SearchError::NotExecutable)
+        );
+        assert_eq!(
+            find_in_path("passwd", "/etc"),
+            Err(SearchError::NotExecutable)
+        );
+        assert_eq!(find_in_path("bin", "/"), Err(SearchError::NotFound));
+    }
+
+    #[test]
+    fn test_spawn_error() {
+        let err = |kind: io::ErrorKind| io::Error::from(kind);
+        assert_eq!(
+            spawn_error("x", &err(io::ErrorKind::PermissionDenied)),
+            ("slush: x: Permission denied".to_string(), 126)
+        );
+        assert_eq!(
+            spawn_error("x", &err(io::ErrorKind::NotFound)),
+            ("slush: x: not found".to_string(), 127)
+        );
+        assert_eq!(
+            spawn_error("x", &io::Error::from_raw_os_error(libc::E2BIG)).1,
+            126
+        );
+    }
+
+    #[test]
+    fn test_path_cache() {
+        let mut cache = PathCache::default();
+        assert_eq!(cache.find("sh", "/bin"), Ok("/bin/sh".into()));
+        assert_eq!(cache.entries().count(), 1);
+        assert_eq!(cache.find("/bin/sh", "/bin"), Ok("/bin/sh".into()));
+        assert_eq!(cache.entries().count(), 1);
+        // a new PATH starts over
+        assert_eq!(cache.find("sh", "/usr/bin"), Ok("/usr/bin/sh".into()));
+        assert_eq!(
+            cache.entries().collect::<Vec<_>>(),
+            vec![&std::path::PathBuf::from("/usr/bin/sh")]
+        );
+        cache.clear();
+        assert_eq!(cache.entries().count(), 0);
+    }
+
+    #[test]
+    fn test_strip_command() {
+        let args = |words: &[&str]| -> Vec<Argument> {
+            words
+                .iter()
+                .map(|w| Argument::Name(w.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            strip_command(&args(&["-p", "ls", "-l"])),
+            Some((true, Argument::Name("ls".to_string()), args(&["-l"])))
+        );
+        assert_eq!(
+            strip_command(&args(&["--", "-x"])),
+            Some((false, Argument::Name("-x".to_string()), Vec::new()))
+        );
+        assert_eq!(strip_command(&args(&["-v", "ls"])), None);
+        assert_eq!(strip_command(&[]), None);
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeSet;
+
+// Everything `set -o` knows about, by long name along with the single
+// letter form if there is one.
+pub const OPTIONS: [(&str, Option<char>); 3] = [
+    // bash style ${name:offset}, ${name/pattern/string} and ${name^^}
+    ("extensions", None),
+    // !! and friends at the prompt, on from the start in interactive shells
+    ("histexpand", Some('H')),
+    // print each command after $PS4 before running it
+    ("xtrace", Some('x')),
+];
+
+#[derive(Debug, Default)]
+pub struct Options {
+    enabled: BTreeSet<&'static str>,
+}
+
+impl Options {
+    pub fn is_set(&self, name: &str) -> bool {
+        self.enabled.contains(name)
+    }
+
+    pub fn set(&mut self, name: &str, on: bool) -> Result<(), String> {
+        let (name, _) = OPTIONS
+            .iter()
+            .find(|(option, _)| *option == name)
+            .ok_or(format!("set: {name}: invalid option name"))?;
+        if on {
+            self.enabled.insert(name);
+        } else {
+            self.enabled.remove(name);
+        }
+        Ok(())
+    }
+
+    // what `set -o` prints, or with restorable set `set +o`
+    pub fn show(&self, restorable: bool) -> Vec<String> {
+        OPTIONS
+            .iter()
+            .map(|(name, _)| {
+                let on = self.is_set(name);
+                if restorable {
+                    format!("set {}o {name}", if on { '-' } else { '+' })
+                } else {
+                    format!("{name:<16}{}", if on { "on" } else { "off" })
+                }
+            })
+            .collect()
+    }
+}
+
+// What `set [-+o name]... [--] [arg...]` was asked to do.
+#[derive(Debug, Default, PartialEq)]
+pub struct SetArgs {
+    pub changes: Vec<(String, bool)>, // option names to turn on or off
+    pub show: Option<bool>,           // -o or +o without a name, see Options::show
+    pub positionals: Option<Vec<String>>,
+}
+
+impl SetArgs {
+    pub fn parse(args: &[String]) -> Result<SetArgs, String> {
+        let mut parsed = SetArgs::default();
+        let mut rest = args;
+        while let Some(arg) = rest.first()
+            && (arg.starts_with('-') || arg.starts_with('+'))
+            && arg.len() > 1
+        {
+            rest = &rest[1..];
+            if arg == "--" {
+                parsed.positionals = Some(Vec::new());
+                break;
+            }
+            let on = arg.starts_with('-');
+            for c in arg.chars().skip(1) {
+                if c != 'o' {
+                    let (name, _) = OPTIONS
+                        .iter()
+                        .find(|(_, flag)| *flag == Some(c))
+                        .ok_or(format!("set: {}{c}: unsupported option", &arg[..1]))?;
+                    parsed.changes.push((name.to_string(), on));
+                    continue;
+                }
+                match rest.first() {
+                    Some(name) if !name.starts_with('-') && !name.starts_with('+') => {
+                        parsed.changes.push((name.clone(), on));
+                        rest = &rest[1..];
+                    }
+                    _ => parsed.show = Some(!on),
+                }
+            }
+        }
+        if !rest.is_empty() {
+            parsed.positionals = Some(rest.to_vec());
+        }
+        Ok(parsed)
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::options::{Options, SetArgs};
+
+    #[allow(dead_code)]
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_set_args() {
+        assert_eq!(
+            SetArgs::parse(&args(&["-o", "extensions", "a", "b"])),
+            Ok(SetArgs {
+                changes: vec![("extensions".to_string(), true)],
+                show: None,
+                positionals: Some(args(&["a", "b"])),
+            })
+        );
+        assert_eq!(
+            SetArgs::parse(&args(&["+o", "extensions", "--"])),
+            Ok(SetArgs {
+                changes: vec![("extensions".to_string(), false)],
+                show: None,
+                positionals: Some(Vec::new()),
+            })
+        );
+        assert_eq!(SetArgs::parse(&args(&["+o"])).unwrap().show, Some(true));
+        assert_eq!(
+            SetArgs::parse(&args(&["-x"])).unwrap().changes,
+            vec![("xtrace".to_string(), true)]
+        );
+        assert!(SetArgs::parse(&args(&["-q"])).is_err());
+    }
+
+    #[test]
+    fn test_options() {
+        let mut options = Options::default();
+        assert!(!options.is_set("extensions"));
+        assert!(options.set("extensions", true).is_ok());
+        assert!(options.is_set("extensions"));
+        assert_eq!(
+            options.show(true),
+            vec!["set -o extensions", "set +o histexpand", "set +o xtrace"]
+        );
+        assert!(options.set("extensions", false).is_ok());
+        assert_eq!(
+            options.show(false),
+            vec![
+                "extensions      off",
+                "histexpand      off",
+                "xtrace          off"
+            ]
+        );
+        assert!(options.set("nonsense", true).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+// Shell glob patterns: * matches any run of characters, ? any single
+// character and [...] one character out of a set. A backslash makes the
+// character after it match only itself.
+
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Char(char),
+    AnyChar,
+    AnyRun,
+    Set(Vec<SetItem>, bool), // true when negated with ! or ^
+}
+
+#[derive(Debug, PartialEq)]
+enum SetItem {
+    Char(char),
+    Range(char, char),
+    Class(String), // [:alpha:] and friends
+}
+
+impl SetItem {
+    fn contains(&self, c: char) -> bool {
+        match self {
+            SetItem::Char(item) => *item == c,
+            SetItem::Range(low, high) => (*low..=*high).contains(&c),
+            SetItem::Class(class) => match class.as_str() {
+                "alnum" => c.is_alphanumeric(),
+                "alpha" => c.is_alphabetic(),
+                "blank" => c == ' ' || c == '\t',
+                "cntrl" => c.is_control(),
+                "digit" => c.is_ascii_digit(),
+                "graph" => c.is_ascii_graphic(),
+                "lower" => c.is_lowercase(),
+                "print" => c.is_ascii_graphic() || c == ' ',
+                "punct" => c.is_ascii_punctuation(),
+                "space" => c.is_whitespace(),
+                "upper" => c.is_uppercase(),
+                "xdigit" => c.is_ascii_hexdigit(),
+                _ => false,
+            },
+        }
+    }
+}
+
+impl Piece {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Piece::Char(item) => *item == c,
+            Piece::AnyChar => true,
+            Piece::AnyRun => false,
+            Piece::Set(items, negated) => items.iter().any(|item| item.contains(c)) != *negated,
+        }
+    }
+}
+
+fn compile(pattern: &str) -> Vec<Piece> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut pieces = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => pieces.push(Piece::AnyRun),
+            '?' => pieces.push(Piece::AnyChar),
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                pieces.push(Piece::Char(chars[i]));
+            }
+            '[' => match compile_set(&chars[i + 1..]) {
+                Some((set, len)) => {
+                    pieces.push(set);
+                    i += len;
+                }
+                // without a closing bracket it is just a bracket
+                None => pieces.push(Piece::Char('[')),
+            },
+            c => pieces.push(Piece::Char(c)),
+        }
+        i += 1;
+    }
+    pieces
+}
+
+// the set after an opening bracket and how many chars it took, closing
+// bracket included
+fn compile_set(chars: &[char]) -> Option<(Piece, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut items = Vec::new();
+    let start = i;
+    while i < chars.len() {
+        let c = chars[i];
+        // a ] right at the start is part of the set
+        if c == ']' && i > start {
+            return Some((Piece::Set(items, negated), i + 1));
+        }
+        if c == '['
+            && chars.get(i + 1) == Some(&':')
+            && let Some(len) = chars[i + 2..].windows(2).position(|w| w == [':', ']'])
+        {
+            items.push(SetItem::Class(chars[i + 2..i + 2 + len].iter().collect()));
+            i += len + 4;
+            continue;
+        }
+        let c = if c == '\\' && i + 1 < chars.len() {
+            i += 1;
+            chars[i]
+        } else {
+            c
+        };
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|c| *c != ']') {
+            items.push(SetItem::Range(c, chars[i + 2]));
+            i += 3;
+        } else {
+            items.push(SetItem::Char(c));
+            i += 1;
+        }
+    }
+    None
+}
+
+pub fn matches(pattern: &str, text: &str) -> bool {
+    matches_pieces(&compile(pattern), &text.chars().collect::<Vec<char>>())
+}
+
+// Every piece but * takes exactly one char, so when something fails after a
+// * it is enough to retry with that * taking one more char.
+fn matches_pieces(pieces: &[Piece], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut retry: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pieces.len() && pieces[p] == Piece::AnyRun {
+            retry = Some((p, t));
+            p += 1;
+        } else if p < pieces.len() && pieces[p].matches(text[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((star, taken)) = retry {
+            p = star + 1;
+            t = taken + 1;
+            retry = Some((star, taken + 1));
+        } else {
+            return false;
+        }
+    }
+    pieces[p..].iter().all(|piece| *piece == Piece::AnyRun)
+}
+
+// Backslashes every char with a special meaning so text matches only
+// itself, used for the quoted parts of a pattern.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// byte offsets where a char starts, plus the end of the text
+fn boundaries(text: &str) -> Vec<usize> {
+    text.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect()
+}
+
+// the value with the shortest (or longest) matching prefix taken off, or
+// the value as is if no prefix matches
+pub fn remove_prefix(text: &str, pattern: &str, longest: bool) -> String {
+    let mut ends = boundaries(text);
+    if longest {
+        ends.reverse();
+    }
+    for end in ends {
+        if matches(pattern, &text[..end]) {
+            return text[end..].to_string();
+        }
+    }
+    text.to_string()
+}
+
+// like remove_prefix from the other end
+pub fn remove_suffix(text: &str, pattern: &str, longest: bool) -> String {
+    let mut starts = boundaries(text);
+    if !longest {
+        starts.reverse();
+    }
+    for start in starts {
+        if matches(pattern, &text[start..]) {
+            return text[..start].to_string();
+        }
+    }
+    text.to_string()
+}
+
+// which match ${name/pattern/string} replaces
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ReplaceMode {
+    First,  // ${name/pattern/string}
+    All,    // ${name//pattern/string}
+    Prefix, // ${name/#pattern/string}
+    Suffix, // ${name/%pattern/string}
+}
+
+// At every spot the longest match is the one replaced, an empty pattern
+// replaces nothing.
+pub fn replace(text: &str, pattern: &str, with: &str, mode: ReplaceMode) -> String {
+    if pattern.is_empty() {
+        return text.to_string();
+    }
+    let bounds = boundaries(text);
+    match mode {
+        ReplaceMode::Prefix => match bounds
+            .iter()
+            .rev()
+            .find(|end| matches(pattern, &text[..**end]))
+        {
+            Some(end) => format!("{with}{}", &text[*end..]),
+            None => text.to_string(),
+        },
+        ReplaceMode::Suffix => match bounds
+            .iter()
+            .find(|start| matches(pattern, &text[**start..]))
+        {
+            Some(start) => format!("{}{with}", &text[..*start]),
+            None => text.to_string(),
+        },
+        ReplaceMode::First | ReplaceMode::All => {
+            let mut replaced = String::new();
+            let mut i = 0;
+            while i + 1 < bounds.len() {
+                let start = bounds[i];
+                let end = bounds[i + 1..]
+                    .iter()
+                    .rposition(|end| matches(pattern, &text[start..*end]));
+                let Some(end) = end.map(|end| i + 1 + end) else {
+                    replaced.push_str(&text[start..bounds[i + 1]]);
+                    i += 1;
+                    continue;
+                };
+                replaced.push_str(with);
+                if mode == ReplaceMode::First {
+                    replaced.push_str(&text[bounds[end]..]);
+                    return replaced;
+                }
+                i = end;
+            }
+            replaced
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CaseChange {
+    UpperFirst, // ${name^}
+    UpperAll,   // ${name^^}
+    LowerFirst, // ${name,}
+    LowerAll,   // ${name,,}
+}
+
+// Changes the case of the chars matching pattern, every char when the
+// pattern is empty. The First forms only look at the first char.
+pub fn change_case(text: &str, pattern: &str, change: CaseChange) -> String {
+    let pattern = if pattern.is_empty() { "?" } else { pattern };
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let all = matches!(change, CaseChange::UpperAll | CaseChange::LowerAll);
+            if (i > 0 && !all) || !matches(pattern, &c.to_string()) {
+                return c.to_string();
+            }
+            match change {
+                CaseChange::UpperFirst | CaseChange::UpperAll => c.to_uppercase().collect(),
+                CaseChange::LowerFirst | CaseChange::LowerAll => c.to_lowercase().collect(),
+            }
+        })
+        .collect()
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::pattern::{
+        CaseChange, ReplaceMode, change_case, escape, matches, remove_prefix, remove_suffix,
+        replace,
+    };
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*.txt", "notes.txt"));
+        assert!(!matches("*.txt", "notes.txt.bak"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "x"));
+    }
+
+    #[test]
+    fn test_sets() {
+        assert!(matches("[abc]", "b"));
+        assert!(!matches("[!abc]", "b"));
+        assert!(matches("[^abc]", "d"));
+        assert!(matches("[a-z]1", "q1"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[[:digit:]]*", "4th"));
+        assert!(!matches("[[:upper:]]", "a"));
+        // an unclosed bracket is taken literally
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+        assert!(matches(&escape("*.txt"), "*.txt"));
+        assert!(!matches(&escape("*.txt"), "a.txt"));
+    }
+
+    #[test]
+    fn test_remove() {
+        let path = "/usr/local/lib.tar.gz";
+        assert_eq!(remove_prefix(path, "*/", false), "usr/local/lib.tar.gz");
+        assert_eq!(remove_prefix(path, "*/", true), "lib.tar.gz");
+        assert_eq!(remove_suffix(path, ".*", false), "/usr/local/lib.tar");
+        assert_eq!(remove_suffix(path, ".*", true), "/usr/local/lib");
+        assert_eq!(remove_suffix(path, "x", true), path);
+        assert_eq!(remove_prefix("héllo", "h?", false), "llo");
+        assert_eq!(remove_prefix("abc", "", true), "abc");
+    }
+
+    #[test]
+    fn test_replace() {
+        let text = "one two one two";
+        assert_eq!(
+            replace(text, "one", "1", ReplaceMode::First),
+            "1 two one two"
+        );
+        assert_eq!(replace(text, "one", "1", ReplaceMode::All), "1 two 1 two");
+        assert_eq!(replace(text, "o*e", "X", ReplaceMode::First), "X two");
+        assert_eq!(replace(text, "t?o", "", ReplaceMode::All), "one  one ");
+        assert_eq!(
+            replace(text, "one", "1", ReplaceMode::Prefix),
+            "1 two one two"
+        );
+        assert_eq!(replace(text, "one", "1", ReplaceMode::Suffix), text);
+        assert_eq!(replace(text, "t*", "2", ReplaceMode::Suffix), "one 2");
+        assert_eq!(replace(text, "", "x", ReplaceMode::All), text);
+    }
+
+    #[test]
+    fn test_change_case() {
+        assert_eq!(
+            change_case("hello world", "", CaseChange::UpperFirst),
+            "Hello world"
+        );
+        assert_eq!(
+            change_case("hello world", "", CaseChange::UpperAll),
+            "HELLO WORLD"
+        );
+        assert_eq!(
+            change_case("hello world", "[lo]", CaseChange::UpperAll),
+            "heLLO wOrLd"
+        );
+        assert_eq!(change_case("ÉCOLE", "", CaseChange::LowerAll), "école");
+        assert_eq!(change_case("ABC", "", CaseChange::LowerFirst), "aBC");
+    }
+}
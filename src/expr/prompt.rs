@@ -0,0 +1,226 @@
+use crate::expr::{Argument, State};
+use nix::unistd::{User, gethostname, getuid};
+use std::cell::RefCell;
+use std::env;
+use std::mem;
+use std::rc::Rc;
+
+// What the prompts are when their variables aren't set. PS1 keeps the
+// status the shell has always shown.
+fn default(name: &str) -> &'static str {
+    match name {
+        "PS1" => "[\\?] \\$ ",
+        "PS2" => "> ",
+        "PS4" => "+ ",
+        _ => "",
+    }
+}
+
+// A prompt cut up at the escapes that show names, what those show is kept
+// apart from the text around it so it is never expanded.
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Text(String),  // expands like a double quoted string
+    Shown(String), // printed as it is
+}
+
+// $PS1, $PS2 or $PS4 ready to print. The backslash escapes are replaced
+// first and the rest expands like a double quoted string, so parameters
+// and command substitutions in it run each time the prompt is shown.
+// What \w and the like show is left out of that, a directory named $(...)
+// is printed rather than run.
+pub fn expand(name: &str, state: &Rc<RefCell<State>>) -> String {
+    let prompt = env::var(name).unwrap_or_else(|_| default(name).to_string());
+    let pieces = escapes(&prompt, &state.borrow(), env::var("HOME").ok().as_deref());
+    let expanded = pieces
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => Argument::QuoteString(text).eval(state),
+            Piece::Shown(shown) => shown,
+        })
+        .collect();
+    // a bad ${name?} in a prompt shouldn't take the next command with it
+    state.borrow_mut().expansion_failed = false;
+    expanded
+}
+
+// `set -x`, shows the words of a command about to run on stderr after
+// $PS4. Tracing is off while PS4 expands so a $(...) in it isn't traced.
+pub fn trace(words: &[String], state: &Rc<RefCell<State>>) {
+    if !state.borrow().options.is_set("xtrace") {
+        return;
+    }
+    let _ = state.borrow_mut().options.set("xtrace", false);
+    let prefix = expand("PS4", state);
+    let _ = state.borrow_mut().options.set("xtrace", true);
+    eprintln!("{prefix}{}", words.join(" "));
+}
+
+fn user() -> String {
+    User::from_uid(getuid())
+        .ok()
+        .flatten()
+        .map(|user| user.name)
+        .or_else(|| env::var("USER").ok())
+        .unwrap_or_default()
+}
+
+fn host() -> String {
+    gethostname()
+        .ok()
+        .and_then(|host| host.into_string().ok())
+        .unwrap_or_default()
+}
+
+// the working directory with $HOME at the start of it shortened to ~
+fn tilde(cwd: &str, home: Option<&str>) -> String {
+    match home {
+        Some(home) if !home.is_empty() && home != "/" => match cwd.strip_prefix(home) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{rest}"),
+            _ => cwd.to_string(),
+        },
+        _ => cwd.to_string(),
+    }
+}
+
+//   \u  user name          \h  host up to the first dot   \H  whole host
+//   \w  working directory  \W  its last part              \j  background jobs
+//   \?  last exit status   \$  # for root, $ for others   \n  newline
+//   \e  escape             \a  bell                        \\  backslash
+//   \[ and \] mark where non-printing text starts and ends, they print nothing.
+// Anything else after a backslash is left alone. The names and \$ come out
+// as pieces of their own, see Piece.
+fn escapes(prompt: &str, state: &State, home: Option<&str>) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut out = String::new();
+    let mut show = |out: &mut String, shown: &str| {
+        pieces.push(Piece::Text(mem::take(out)));
+        pieces.push(Piece::Shown(shown.to_string()));
+    };
+    let mut chars = prompt.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => show(&mut out, &user()),
+            Some('h') => show(&mut out, host().split('.').next().unwrap_or_default()),
+            Some('H') => show(&mut out, &host()),
+            Some('w') => show(&mut out, &tilde(&state.cwd, home)),
+            Some('W') => {
+                let cwd = tilde(&state.cwd, home);
+                show(
+                    &mut out,
+                    match cwd.rsplit_once('/') {
+                        Some((_, "")) | None => &cwd,
+                        Some((_, last)) => last,
+                    },
+                );
+            }
+            Some('j') => out += &state.bg_jobs.len().to_string(),
+            Some('?') => out += &state.prev_status.to_string(),
+            Some('$') => show(&mut out, if getuid().is_root() { "#" } else { "$" }),
+            Some('n') => out.push('\n'),
+            Some('e') => out.push('\x1b'),
+            Some('a') => out.push('\x07'),
+            Some('\\') => out.push('\\'),
+            Some('[') | Some(']') => {}
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            None => out.push('\\'),
+        }
+    }
+    pieces.push(Piece::Text(out));
+    pieces
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::State;
+    #[allow(unused_imports)]
+    use crate::expr::prompt::{Piece, escapes, expand, tilde};
+    #[allow(unused_imports)]
+    use std::env;
+
+    #[test]
+    fn test_tilde() {
+        assert_eq!(tilde("/home/me", Some("/home/me")), "~");
+        assert_eq!(tilde("/home/me/src", Some("/home/me")), "~/src");
+        assert_eq!(tilde("/home/meant", Some("/home/me")), "/home/meant");
+        assert_eq!(tilde("/tmp", None), "/tmp");
+    }
+
+    #[test]
+    fn test_escapes() {
+        let state = State::new();
+        {
+            let mut state = state.borrow_mut();
+            state.cwd = "/home/me/src".to_string();
+            state.prev_status = 3;
+        }
+        let state = state.borrow();
+        let home = Some("/home/me");
+        let shown = |prompt: &str| -> String {
+            escapes(prompt, &state, home)
+                .into_iter()
+                .map(|piece| match piece {
+                    Piece::Text(text) | Piece::Shown(text) => text,
+                })
+                .collect()
+        };
+        assert_eq!(shown("[\\?] \\w"), "[3] ~/src");
+        assert_eq!(shown("\\W \\j"), "src 0");
+        assert_eq!(shown("\\[\\e[1m\\]\\\\\\q"), "\x1b[1m\\\\q");
+        assert_eq!(shown("trailing \\"), "trailing \\");
+        assert_eq!(
+            escapes("$x\\w\u{e0b0}", &state, home),
+            [
+                Piece::Text("$x".to_string()),
+                Piece::Shown("~/src".to_string()),
+                Piece::Text("\u{e0b0}".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand() {
+        let state = State::new();
+        state.borrow_mut().prev_status = 1;
+        state
+            .borrow()
+            .set_variable("SLUSH_PROMPT_TEST", "\\? ${SLUSH_PROMPT_WORD:-none} # $ ")
+            .unwrap();
+        assert_eq!(expand("SLUSH_PROMPT_TEST", &state), "1 none # $ ");
+
+        // a directory name is shown as it is, never expanded
+        state.borrow_mut().cwd = "/tmp/$(echo PWNED >&2)/`echo x`/${HOME}\\\"".to_string();
+        state
+            .borrow()
+            .set_variable("SLUSH_PROMPT_TEST", "\\w %% \\W")
+            .unwrap();
+        assert_eq!(
+            expand("SLUSH_PROMPT_TEST", &state),
+            "/tmp/$(echo PWNED >&2)/`echo x`/${HOME}\\\" %% ${HOME}\\\""
+        );
+
+        // powerline and nerd font glyphs live in the private use area
+        state
+            .borrow()
+            .set_variable("SLUSH_PROMPT_TEST", "\u{e0b0}\u{E000} \\$ \u{f115}")
+            .unwrap();
+        assert_eq!(
+            expand("SLUSH_PROMPT_TEST", &state),
+            format!(
+                "\u{e0b0}\u{E000} {} \u{f115}",
+                if nix::unistd::getuid().is_root() {
+                    '#'
+                } else {
+                    '$'
+                }
+            )
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use crate::expr::getopts::{GetoptsResult, GetoptsState};
+use nix::libc;
+use std::io;
+use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
+
+// what `read [-r] [-p prompt] [-t timeout] [-d delim] [name...]` was asked to do
+#[derive(Debug, PartialEq)]
+pub struct ReadArgs {
+    pub raw: bool,
+    pub prompt: Option<String>,
+    pub timeout: Option<Duration>,
+    pub delim: char,
+    pub names: Vec<String>,
+}
+
+impl ReadArgs {
+    pub fn parse(args: &[String]) -> Result<ReadArgs, String> {
+        let mut parsed = ReadArgs {
+            raw: false,
+            prompt: None,
+            timeout: None,
+            delim: '\n',
+            names: Vec::new(),
+        };
+        let mut opts = GetoptsState::default();
+        loop {
+            let optind = opts.optind();
+            match opts.next(":rp:t:d:", args, optind) {
+                GetoptsResult::Opt('r', _) => parsed.raw = true,
+                GetoptsResult::Opt('p', prompt) => parsed.prompt = prompt,
+                GetoptsResult::Opt('t', Some(timeout)) => {
+                    let seconds = timeout
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|seconds| *seconds >= 0.0)
+                        .ok_or(format!("read: {timeout}: invalid timeout"))?;
+                    parsed.timeout = Some(Duration::from_secs_f64(seconds));
+                }
+                // an empty delimiter means NUL, like bash
+                GetoptsResult::Opt('d', Some(delim)) => {
+                    parsed.delim = delim.chars().next().unwrap_or('\0')
+                }
+                GetoptsResult::Opt(c, _) | GetoptsResult::Illegal(c) => {
+                    return Err(format!("read: -{c}: invalid option"));
+                }
+                GetoptsResult::MissingArg(c) => {
+                    return Err(format!("read: -{c}: option requires an argument"));
+                }
+                GetoptsResult::Done => break,
+            }
+        }
+        parsed.names = args[opts.optind().max(1) - 1..].to_vec();
+        Ok(parsed)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LineEnd {
+    Delim,   // found the delimiter
+    Eof,     // ran out of input first
+    Timeout, // -t ran out first
+}
+
+// A line as read, every char remembers whether it was escaped with a
+// backslash so field splitting can leave it alone.
+pub type ReadLine = Vec<(char, bool)>;
+
+// Reads up to the delimiter one byte at a time, the rest of the input is
+// left for whatever runs after us.
+pub fn read_line(
+    fd: RawFd,
+    delim: char,
+    raw: bool,
+    timeout: Option<Duration>,
+) -> io::Result<(ReadLine, LineEnd)> {
+    let mut reader = FdChars::new(fd, timeout);
+    let mut line = Vec::new();
+    loop {
+        let c = match reader.next_char()? {
+            Some(Some(c)) => c,
+            Some(None) => return Ok((line, LineEnd::Eof)),
+            None => return Ok((line, LineEnd::Timeout)),
+        };
+        if c == delim {
+            return Ok((line, LineEnd::Delim));
+        }
+        if c != '\\' || raw {
+            line.push((c, false));
+            continue;
+        }
+        match reader.next_char()? {
+            // backslash newline continues the line
+            Some(Some('\n')) => {}
+            Some(Some(c)) => line.push((c, true)),
+            Some(None) => return Ok((line, LineEnd::Eof)),
+            None => return Ok((line, LineEnd::Timeout)),
+        }
+    }
+}
+
+// Decodes UTF-8 straight off a file descriptor without buffering ahead,
+// also used by the line editor to read keys.
+pub struct FdChars {
+    fd: RawFd,
+    deadline: Option<Instant>,
+}
+
+impl FdChars {
+    pub fn new(fd: RawFd, timeout: Option<Duration>) -> FdChars {
+        FdChars {
+            fd,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+        }
+    }
+
+    // None when the deadline passed, Some(None) at the end of input
+    pub fn next_char(&mut self) -> io::Result<Option<Option<char>>> {
+        let Some(first) = self.next_byte()? else {
+            return Ok(None);
+        };
+        let Some(first) = first else {
+            return Ok(Some(None));
+        };
+        let len = match first {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        let mut bytes = vec![first];
+        while bytes.len() < len {
+            match self.next_byte()? {
+                Some(Some(byte)) => bytes.push(byte),
+                _ => break,
+            }
+        }
+        let decoded = String::from_utf8_lossy(&bytes);
+        Ok(Some(decoded.chars().next()))
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<Option<u8>>> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut pollfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as libc::c_int) };
+            if ready < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if ready == 0 {
+                return Ok(None);
+            }
+        }
+        let mut byte = 0u8;
+        loop {
+            let count =
+                unsafe { libc::read(self.fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            if count >= 0 {
+                return Ok(Some((count == 1).then_some(byte)));
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+}
+
+// Splits a line into count fields on the characters in ifs. Whitespace in
+// ifs is trimmed from both ends and runs of it count as one separator, the
+// last field gets the rest of the line, separators and all.
+pub fn split_fields(line: &[(char, bool)], ifs: &str, count: usize) -> Vec<String> {
+    let is_sep = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+    let is_space = |item: &(char, bool)| is_sep(item) && " \t\n".contains(item.0);
+    let mut start = line.iter().position(|c| !is_space(c)).unwrap_or(line.len());
+    let end = line
+        .iter()
+        .rposition(|c| !is_space(c))
+        .map_or(0, |end| end + 1);
+    let mut fields = Vec::new();
+    while fields.len() + 1 < count && start < end {
+        let field_end = line[start..end]
+            .iter()
+            .position(is_sep)
+            .map_or(end, |pos| start + pos);
+        fields.push(line[start..field_end].iter().map(|(c, _)| *c).collect());
+        // whitespace around a separator belongs to it
+        start = field_end;
+        while start < end && is_space(&line[start]) {
+            start += 1;
+        }
+        if start < end && is_sep(&line[start]) && !is_space(&line[start]) {
+            start += 1;
+            while start < end && is_space(&line[start]) {
+                start += 1;
+            }
+        }
+    }
+    if count > 0 {
+        fields.push(line[start.min(end)..end].iter().map(|(c, _)| *c).collect());
+    }
+    fields.resize(count, String::new());
+    fields
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::read::{ReadArgs, split_fields};
+    #[allow(unused_imports)]
+    use std::time::Duration;
+
+    #[allow(dead_code)]
+    fn plain(line: &str) -> Vec<(char, bool)> {
+        line.chars().map(|c| (c, false)).collect()
+    }
+
+    #[test]
+    fn test_parse_read_args() {
+        let args: Vec<String> = ["-r", "-p", "name? ", "-t1.5", "first", "rest"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(
+            ReadArgs::parse(&args),
+            Ok(ReadArgs {
+                raw: true,
+                prompt: Some("name? ".to_string()),
+                timeout: Some(Duration::from_millis(1500)),
+                delim: '\n',
+                names: vec!["first".to_string(), "rest".to_string()],
+            })
+        );
+        let args = vec!["-d".to_string(), "".to_string()];
+        let parsed = ReadArgs::parse(&args).unwrap();
+        assert_eq!(parsed.delim, '\0');
+        assert!(parsed.names.is_empty());
+        assert!(ReadArgs::parse(&["-x".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_split_whitespace() {
+        let line = plain("  one  two   three four  ");
+        assert_eq!(
+            split_fields(&line, " \t\n", 1),
+            vec!["one  two   three four"]
+        );
+        assert_eq!(
+            split_fields(&line, " \t\n", 2),
+            vec!["one", "two   three four"]
+        );
+        assert_eq!(
+            split_fields(&line, " \t\n", 5),
+            vec!["one", "two", "three", "four", ""]
+        );
+    }
+
+    #[test]
+    fn test_split_other_separators() {
+        let line = plain("a:b::c");
+        assert_eq!(split_fields(&line, ":", 4), vec!["a", "b", "", "c"]);
+        assert_eq!(split_fields(&line, ":", 2), vec!["a", "b::c"]);
+        let line = plain("a : b");
+        assert_eq!(split_fields(&line, ": ", 2), vec!["a", "b"]);
+        assert_eq!(split_fields(&plain("a b"), "", 2), vec!["a b", ""]);
+    }
+
+    #[test]
+    fn test_split_escaped() {
+        let mut line = plain("a b");
+        line[1].1 = true;
+        assert_eq!(split_fields(&line, " ", 2), vec!["a b", ""]);
+    }
+}
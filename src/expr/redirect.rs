@@ -0,0 +1,149 @@
+use crate::expr::{RedirectExpr, RedirectType, State};
+use nix::libc;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+
+// file descriptors the shell holds on to for itself are kept at or above
+// this number so they don't collide with anything a user can redirect.
+const FIRST_SHELL_FD: RawFd = 10;
+
+// A redirect after its file has been opened, this can be applied to the
+// shell itself or to a child right before it calls exec.
+pub enum FdAction {
+    Open(OwnedFd, RawFd), // point fd at an opened file
+    Copy(RawFd, RawFd),   // n>&m
+    Close(RawFd),         // n>&-
+}
+
+impl FdAction {
+    fn target(&self) -> RawFd {
+        match self {
+            FdAction::Open(_, target) | FdAction::Copy(_, target) | FdAction::Close(target) => {
+                *target
+            }
+        }
+    }
+
+    // only uses async-signal-safe calls, so this is okay to run between fork and exec
+    fn apply(&self) -> io::Result<()> {
+        let ret = unsafe {
+            match self {
+                FdAction::Open(file, target) => libc::dup2(file.as_raw_fd(), *target),
+                FdAction::Copy(source, target) => libc::dup2(*source, *target),
+                FdAction::Close(target) => {
+                    libc::close(*target);
+                    0
+                }
+            }
+        };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// duplicate fd somewhere out of the way, the copy is closed on exec.
+pub fn dup_fd(fd: RawFd) -> Option<OwnedFd> {
+    let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, FIRST_SHELL_FD) };
+    if copy < 0 {
+        None
+    } else {
+        Some(unsafe { OwnedFd::from_raw_fd(copy) })
+    }
+}
+
+pub fn resolve(
+    redirects: &[RedirectExpr],
+    state: &Rc<RefCell<State>>,
+) -> Result<Vec<FdAction>, String> {
+    let mut actions = Vec::new();
+    for redirect in redirects {
+        let target = redirect.file_descriptor as RawFd;
+        let word = redirect.file.eval(state);
+        state.borrow().check_expansion()?;
+        let file = match redirect.mode {
+            RedirectType::Out => File::create(&word),
+            RedirectType::OutAppend => File::options().append(true).create(true).open(&word),
+            RedirectType::In => File::open(&word),
+            RedirectType::OutDup | RedirectType::InDup => {
+                if word == "-" {
+                    actions.push(FdAction::Close(target));
+                } else if let Ok(source) = word.parse::<RawFd>() {
+                    actions.push(FdAction::Copy(source, target));
+                } else {
+                    return Err(format!("slush: {word}: bad file descriptor number"));
+                }
+                continue;
+            }
+        };
+        let file = match file {
+            Ok(f) => f,
+            Err(e) => return Err(format!("slush: cannot open {word}: {e}")),
+        };
+        // the file could have landed on the very fd we are about to replace
+        match dup_fd(file.as_raw_fd()) {
+            Some(fd) => actions.push(FdAction::Open(fd, target)),
+            None => return Err(format!("slush: {word}: {}", io::Error::last_os_error())),
+        }
+    }
+    Ok(actions)
+}
+
+// used by a child process in pre_exec, nothing to restore after this.
+pub fn apply_in_child(actions: &[FdAction]) -> io::Result<()> {
+    for action in actions {
+        action.apply()?;
+    }
+    Ok(())
+}
+
+pub fn redirects_fd(actions: &[FdAction], fd: RawFd) -> bool {
+    actions.iter().any(|action| action.target() == fd)
+}
+
+// Redirects applied to the shell process itself, for builtins and functions.
+// The original file descriptors are put back when this is dropped unless
+// persist is called (which is what `exec` without a command does).
+pub struct SavedFds {
+    saved: Vec<(RawFd, Option<OwnedFd>)>,
+}
+
+impl SavedFds {
+    pub fn apply(actions: &[FdAction]) -> Result<SavedFds, String> {
+        let _ = io::stdout().flush();
+        let mut saved = SavedFds { saved: Vec::new() };
+        for action in actions {
+            let target = action.target();
+            if !saved.saved.iter().any(|(fd, _)| *fd == target) {
+                saved.saved.push((target, dup_fd(target)));
+            }
+            if let Err(e) = action.apply() {
+                return Err(format!("slush: {target}: {e}"));
+            }
+        }
+        Ok(saved)
+    }
+
+    pub fn persist(mut self) {
+        self.saved.clear();
+    }
+}
+
+impl Drop for SavedFds {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        while let Some((target, original)) = self.saved.pop() {
+            unsafe {
+                match original {
+                    Some(fd) => libc::dup2(fd.as_raw_fd(), target),
+                    None => libc::close(target),
+                };
+            }
+        }
+    }
+}
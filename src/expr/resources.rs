@@ -0,0 +1,280 @@
+use nix::sys::resource::{
+    RLIM_INFINITY, Resource, UsageWho, getrlimit, getrusage, rlim_t, setrlimit,
+};
+use nix::sys::time::{TimeVal, TimeValLike};
+
+// A limit `ulimit` knows about. Values are shown to the user in units of
+// factor bytes (blocks or kbytes) while the kernel counts plain bytes.
+#[derive(Debug, PartialEq)]
+pub struct Limit {
+    pub flag: char,
+    pub name: &'static str,
+    resource: Resource,
+    factor: rlim_t,
+}
+
+// in the order `ulimit -a` lists them
+pub const LIMITS: [Limit; 7] = [
+    Limit {
+        flag: 't',
+        name: "time(seconds)",
+        resource: Resource::RLIMIT_CPU,
+        factor: 1,
+    },
+    Limit {
+        flag: 'f',
+        name: "file(blocks)",
+        resource: Resource::RLIMIT_FSIZE,
+        factor: 512,
+    },
+    Limit {
+        flag: 'd',
+        name: "data(kbytes)",
+        resource: Resource::RLIMIT_DATA,
+        factor: 1024,
+    },
+    Limit {
+        flag: 's',
+        name: "stack(kbytes)",
+        resource: Resource::RLIMIT_STACK,
+        factor: 1024,
+    },
+    Limit {
+        flag: 'c',
+        name: "coredump(blocks)",
+        resource: Resource::RLIMIT_CORE,
+        factor: 512,
+    },
+    Limit {
+        flag: 'n',
+        name: "nofiles",
+        resource: Resource::RLIMIT_NOFILE,
+        factor: 1,
+    },
+    Limit {
+        flag: 'v',
+        name: "vmemory(kbytes)",
+        resource: Resource::RLIMIT_AS,
+        factor: 1024,
+    },
+];
+
+// What `ulimit [-HSa] [-cdfnstv] [limit]` was asked to do. With neither -H
+// nor -S the soft limit is shown and both limits are set.
+#[derive(Debug, PartialEq)]
+pub struct UlimitArgs {
+    pub hard: bool,
+    pub soft: bool,
+    pub all: bool,
+    pub limit: &'static Limit,
+    pub value: Option<String>,
+}
+
+impl UlimitArgs {
+    pub fn parse(args: &[String]) -> Result<UlimitArgs, String> {
+        let mut parsed = UlimitArgs {
+            hard: false,
+            soft: false,
+            all: false,
+            limit: &LIMITS[1],
+            value: None,
+        };
+        let mut rest = args;
+        while let Some(opt) = rest.first()
+            && opt.starts_with('-')
+            && opt.len() > 1
+        {
+            rest = &rest[1..];
+            for c in opt.chars().skip(1) {
+                match c {
+                    'H' => parsed.hard = true,
+                    'S' => parsed.soft = true,
+                    'a' => parsed.all = true,
+                    c => {
+                        parsed.limit = LIMITS
+                            .iter()
+                            .find(|limit| limit.flag == c)
+                            .ok_or(format!("ulimit: -{c}: invalid option"))?;
+                    }
+                }
+            }
+        }
+        match rest {
+            [] => {}
+            [value] => parsed.value = Some(value.clone()),
+            _ => return Err("ulimit: too many arguments".to_string()),
+        }
+        Ok(parsed)
+    }
+}
+
+impl Limit {
+    pub fn show(&self, hard: bool) -> Result<String, String> {
+        let (soft_limit, hard_limit) =
+            getrlimit(self.resource).map_err(|e| format!("ulimit: {e}"))?;
+        let value = if hard { hard_limit } else { soft_limit };
+        if value == RLIM_INFINITY {
+            Ok("unlimited".to_string())
+        } else {
+            Ok((value / self.factor).to_string())
+        }
+    }
+
+    pub fn set(&self, value: &str, hard: bool, soft: bool) -> Result<(), String> {
+        let value = if value == "unlimited" {
+            RLIM_INFINITY
+        } else {
+            value
+                .parse::<rlim_t>()
+                .ok()
+                .and_then(|value| value.checked_mul(self.factor))
+                .ok_or(format!("ulimit: {value}: bad number"))?
+        };
+        let (mut soft_limit, mut hard_limit) =
+            getrlimit(self.resource).map_err(|e| format!("ulimit: {e}"))?;
+        // setting without -H or -S sets both
+        if hard || !soft {
+            hard_limit = value;
+        }
+        if soft || !hard {
+            soft_limit = value;
+        }
+        setrlimit(self.resource, soft_limit, hard_limit).map_err(|e| format!("ulimit: {e}"))
+    }
+}
+
+// formats cpu time the way `times` prints it, 0m1.250000s
+pub fn format_cpu_time(time: TimeVal) -> String {
+    let micros = time.num_microseconds();
+    let minutes = micros / 60_000_000;
+    let seconds = (micros % 60_000_000) as f64 / 1_000_000.0;
+    format!("{minutes}m{seconds:.6}s")
+}
+
+// what `time -p` prints, and what `time` prints when TIMEFORMAT isn't set
+pub const POSIX_TIMEFORMAT: &str = "real %2R\nuser %2U\nsys %2S";
+pub const DEFAULT_TIMEFORMAT: &str = "\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS";
+
+// user and system seconds used by the shell and all the children it waited
+// on, builtins and functions run inside the shell so both are needed
+pub fn cpu_usage() -> (f64, f64) {
+    let seconds = |time: TimeVal| time.num_microseconds() as f64 / 1_000_000.0;
+    [UsageWho::RUSAGE_SELF, UsageWho::RUSAGE_CHILDREN]
+        .into_iter()
+        .filter_map(|who| getrusage(who).ok())
+        .fold((0.0, 0.0), |(user, sys), usage| {
+            (
+                user + seconds(usage.user_time()),
+                sys + seconds(usage.system_time()),
+            )
+        })
+}
+
+// Expands a TIMEFORMAT string. %R, %U and %S are the real, user and system
+// seconds, %P is the cpu percentage. An optional precision digit (at most 3)
+// and an l for the long 1m2.345s form can go between the % and the letter.
+pub fn format_timing(format: &str, real: f64, user: f64, sys: f64) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut precision = 3;
+        if let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+            precision = digit.min(3) as usize;
+            chars.next();
+        }
+        let long = chars.next_if_eq(&'l').is_some();
+        let value = match chars.next() {
+            Some('R') => real,
+            Some('U') => user,
+            Some('S') => sys,
+            Some('P') => {
+                let percent = if real > 0.0 {
+                    (user + sys) / real * 100.0
+                } else {
+                    0.0
+                };
+                out.push_str(&format!("{percent:.precision$}"));
+                continue;
+            }
+            Some('%') => {
+                out.push('%');
+                continue;
+            }
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+                continue;
+            }
+            None => {
+                out.push('%');
+                break;
+            }
+        };
+        if long {
+            let minutes = (value / 60.0).floor();
+            out.push_str(&format!(
+                "{minutes}m{:.precision$}s",
+                value - minutes * 60.0
+            ));
+        } else {
+            out.push_str(&format!("{value:.precision$}"));
+        }
+    }
+    out
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::resources::{
+        DEFAULT_TIMEFORMAT, LIMITS, POSIX_TIMEFORMAT, UlimitArgs, format_cpu_time, format_timing,
+    };
+    #[allow(unused_imports)]
+    use nix::sys::time::{TimeVal, TimeValLike};
+
+    #[test]
+    fn test_parse_ulimit_args() {
+        let args =
+            |words: &[&str]| -> Vec<String> { words.iter().map(|w| w.to_string()).collect() };
+        assert_eq!(
+            UlimitArgs::parse(&args(&["-Hn", "4096"])),
+            Ok(UlimitArgs {
+                hard: true,
+                soft: false,
+                all: false,
+                limit: &LIMITS[5],
+                value: Some("4096".to_string()),
+            })
+        );
+        let parsed = UlimitArgs::parse(&[]).unwrap();
+        assert_eq!(parsed.limit.flag, 'f');
+        assert!(parsed.value.is_none());
+        assert!(UlimitArgs::parse(&args(&["-q"])).is_err());
+        assert!(UlimitArgs::parse(&args(&["-n", "1", "2"])).is_err());
+    }
+
+    #[test]
+    fn test_format_cpu_time() {
+        assert_eq!(format_cpu_time(TimeVal::microseconds(0)), "0m0.000000s");
+        assert_eq!(
+            format_cpu_time(TimeVal::microseconds(61_250_000)),
+            "1m1.250000s"
+        );
+    }
+
+    #[test]
+    fn test_format_timing() {
+        assert_eq!(
+            format_timing(POSIX_TIMEFORMAT, 1.5, 0.25, 0.125),
+            "real 1.50\nuser 0.25\nsys 0.12"
+        );
+        assert_eq!(
+            format_timing(DEFAULT_TIMEFORMAT, 61.5, 0.0, 0.0),
+            "\nreal\t1m1.500s\nuser\t0m0.000s\nsys\t0m0.000s"
+        );
+        assert_eq!(format_timing("%0R %P%% %q", 2.0, 0.5, 0.5), "2 50.000% %q");
+    }
+}
@@ -0,0 +1,86 @@
+use std::cell::Cell;
+use std::process;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// $RANDOM, a new number from 0 to 32767 every time it is read. Assigning to
+// RANDOM seeds it so the same numbers come out again.
+#[derive(Debug)]
+pub struct Random {
+    seed: Cell<u32>,
+}
+
+impl Default for Random {
+    fn default() -> Random {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.subsec_nanos());
+        Random {
+            seed: Cell::new(nanos ^ process::id()),
+        }
+    }
+}
+
+impl Random {
+    pub fn seed(&self, seed: u32) {
+        self.seed.set(seed);
+    }
+
+    // the same linear congruential generator as the C standard's rand example
+    pub fn next(&self) -> u32 {
+        let seed = self.seed.get().wrapping_mul(1103515245).wrapping_add(12345);
+        self.seed.set(seed);
+        (seed >> 16) & 0x7fff
+    }
+}
+
+// $SECONDS, how long the shell has been running. Assigning to SECONDS
+// makes it count up from that value instead.
+#[derive(Debug)]
+pub struct Seconds {
+    since: Cell<Instant>,
+    base: Cell<u64>,
+}
+
+impl Default for Seconds {
+    fn default() -> Seconds {
+        Seconds {
+            since: Cell::new(Instant::now()),
+            base: Cell::new(0),
+        }
+    }
+}
+
+impl Seconds {
+    pub fn reset(&self, base: u64) {
+        self.since.set(Instant::now());
+        self.base.set(base);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.base.get() + self.since.get().elapsed().as_secs()
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::special::{Random, Seconds};
+
+    #[test]
+    fn test_random_seed() {
+        let random = Random::default();
+        random.seed(42);
+        let first: Vec<u32> = (0..5).map(|_| random.next()).collect();
+        assert!(first.iter().all(|n| *n <= 32767));
+        random.seed(42);
+        let again: Vec<u32> = (0..5).map(|_| random.next()).collect();
+        assert_eq!(first, again);
+    }
+
+    #[test]
+    fn test_seconds() {
+        let seconds = Seconds::default();
+        assert_eq!(seconds.get(), 0);
+        seconds.reset(100);
+        assert_eq!(seconds.get(), 100);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::expr::State;
+use crate::parser::Parser;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::process;
+use std::rc::Rc;
+
+// What `trap [action condition...]` was asked to do. Only EXIT can be
+// trapped, signals are turned away.
+#[derive(Debug, PartialEq)]
+pub enum TrapArgs {
+    Show,                // no operands, print the traps set
+    Set(Option<String>), // None puts the default back, `trap - EXIT`
+}
+
+pub fn parse(args: &[String]) -> Result<TrapArgs, String> {
+    let operands = match args {
+        [first, rest @ ..] if first == "--" => rest,
+        _ => args,
+    };
+    let Some((action, conditions)) = operands.split_first() else {
+        return Ok(TrapArgs::Show);
+    };
+    // a first operand that is a number is a condition, they are all reset
+    let (action, conditions) = if !action.is_empty() && action.chars().all(|c| c.is_ascii_digit()) {
+        (None, operands)
+    } else if action == "-" {
+        (None, conditions)
+    } else {
+        (Some(action.clone()), conditions)
+    };
+    if conditions.is_empty() {
+        return Err("trap: condition expected".to_string());
+    }
+    match conditions.iter().find(|c| *c != "EXIT" && *c != "0") {
+        Some(signal) => Err(format!("trap: {signal}: only EXIT can be trapped")),
+        None => Ok(TrapArgs::Set(action)),
+    }
+}
+
+// `trap` on its own lists them in a form that can be run again
+pub fn show(action: &str) -> String {
+    format!("trap -- '{}' EXIT", action.replace('\'', "'\\''"))
+}
+
+// Runs the EXIT trap, at most once. $? in it is the status the shell is
+// leaving with, and is left as it was afterwards.
+pub fn run_exit_trap(state: &Rc<RefCell<State>>) {
+    let Some(action) = state.borrow_mut().exit_trap.take() else {
+        return;
+    };
+    let mut parser = Parser::new(state.clone());
+    parser.first_line = state.borrow().lineno.max(1);
+    parser.parse(&action);
+    if !parser.err.is_empty() {
+        eprintln!("slush: {}", parser.err);
+        return;
+    }
+    let status = state.borrow().prev_status;
+    state.borrow_mut().expansion_failed = false;
+    for mut expr in parser.exprs {
+        match expr.eval() {
+            Ok(status) => state.borrow_mut().prev_status = status,
+            Err(_) if state.borrow().expansion_failed => break,
+            Err(err) => eprintln!("{err}"),
+        }
+    }
+    state.borrow_mut().prev_status = status;
+}
+
+// Every way out of the shell goes through here so the trap gets to run.
+pub fn exit(state: &Rc<RefCell<State>>, status: i32) -> ! {
+    state.borrow_mut().prev_status = status;
+    run_exit_trap(state);
+    let _ = io::stdout().flush();
+    process::exit(status);
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::trap::{TrapArgs, parse, show};
+
+    #[test]
+    fn test_parse() {
+        let args =
+            |words: &[&str]| -> Vec<String> { words.iter().map(|w| w.to_string()).collect() };
+        assert_eq!(parse(&args(&[])), Ok(TrapArgs::Show));
+        assert_eq!(
+            parse(&args(&["echo bye", "EXIT"])),
+            Ok(TrapArgs::Set(Some("echo bye".to_string())))
+        );
+        assert_eq!(
+            parse(&args(&["--", "", "0"])),
+            Ok(TrapArgs::Set(Some(String::new())))
+        );
+        assert_eq!(parse(&args(&["-", "EXIT"])), Ok(TrapArgs::Set(None)));
+        assert_eq!(parse(&args(&["0"])), Ok(TrapArgs::Set(None)));
+        assert_eq!(parse(&args(&["--"])), Ok(TrapArgs::Show));
+        assert!(parse(&args(&["echo", "INT"])).is_err());
+        assert!(parse(&args(&["echo"])).is_err());
+    }
+
+    #[test]
+    fn test_show() {
+        assert_eq!(show("echo 'bye'"), "trap -- 'echo '\\''bye'\\''' EXIT");
+    }
+}
@@ -0,0 +1,112 @@
+use nix::libc::mode_t;
+
+// Masks are plain permission bits here, 0o022 and so on.
+
+pub fn format_octal(mask: mode_t) -> String {
+    format!("{:04o}", mask & 0o777)
+}
+
+// `umask -S` shows the permissions that are let through rather than the
+// ones being masked, u=rwx,g=rx,o=rx for 022.
+pub fn format_symbolic(mask: mode_t) -> String {
+    let allowed = !mask & 0o777;
+    let class = |shift: mode_t| -> String {
+        let bits = (allowed >> shift) & 0o7;
+        [(0o4, 'r'), (0o2, 'w'), (0o1, 'x')]
+            .iter()
+            .filter(|(bit, _)| bits & bit != 0)
+            .map(|(_, c)| *c)
+            .collect()
+    };
+    format!("u={},g={},o={}", class(6), class(3), class(0))
+}
+
+// Either an octal number or a chmod style symbolic mode like u=rwx,g+w,o-r.
+// Symbolic modes are applied on top of the current mask.
+pub fn parse(mode: &str, current: mode_t) -> Result<mode_t, String> {
+    if !mode.is_empty() && mode.chars().all(|c| c.is_digit(8)) {
+        return mode_t::from_str_radix(mode, 8)
+            .ok()
+            .filter(|mask| *mask <= 0o777)
+            .ok_or(format!("umask: {mode}: illegal number"));
+    }
+    let illegal = || format!("umask: {mode}: illegal mode");
+    let mut allowed = !current & 0o777;
+    for clause in mode.split(',') {
+        let who_end = clause.find(|c| !"ugoa".contains(c)).ok_or_else(illegal)?;
+        let who = match who_end {
+            0 => 0o777,
+            _ => clause[..who_end].chars().fold(0, |who, c| {
+                who | match c {
+                    'u' => 0o700,
+                    'g' => 0o070,
+                    'o' => 0o007,
+                    _ => 0o777,
+                }
+            }),
+        };
+        let mut rest = &clause[who_end..];
+        while let Some(op) = rest.chars().next() {
+            rest = &rest[1..];
+            let perm_end = rest.find(['+', '-', '=']).unwrap_or(rest.len());
+            let bits = perm_bits(&rest[..perm_end], allowed).ok_or_else(illegal)? & who;
+            rest = &rest[perm_end..];
+            match op {
+                '+' => allowed |= bits,
+                '-' => allowed &= !bits,
+                '=' => allowed = (allowed & !who) | bits,
+                _ => return Err(illegal()),
+            }
+        }
+    }
+    Ok(!allowed & 0o777)
+}
+
+// the bits named by rwx (or copied from a class with u, g or o) set for all
+// three classes, the caller narrows it down to who the clause is for
+fn perm_bits(perms: &str, allowed: mode_t) -> Option<mode_t> {
+    let mut bits = 0;
+    for c in perms.chars() {
+        bits |= match c {
+            'r' => 0o444,
+            'w' => 0o222,
+            'x' | 'X' => 0o111,
+            's' | 't' => 0,
+            'u' => ((allowed >> 6) & 0o7) * 0o111,
+            'g' => ((allowed >> 3) & 0o7) * 0o111,
+            'o' => (allowed & 0o7) * 0o111,
+            _ => return None,
+        };
+    }
+    Some(bits)
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::expr::umask::{format_octal, format_symbolic, parse};
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_octal(0o22), "0022");
+        assert_eq!(format_symbolic(0o22), "u=rwx,g=rx,o=rx");
+        assert_eq!(format_symbolic(0o377), "u=r,g=,o=");
+    }
+
+    #[test]
+    fn test_parse_octal() {
+        assert_eq!(parse("027", 0o22), Ok(0o27));
+        assert_eq!(parse("0", 0o22), Ok(0));
+        assert!(parse("1777", 0o22).is_err());
+    }
+
+    #[test]
+    fn test_parse_symbolic() {
+        assert_eq!(parse("u=rwx,g=rx,o=", 0o22), Ok(0o27));
+        assert_eq!(parse("g+w", 0o27), Ok(0o07));
+        assert_eq!(parse("a-w", 0o07), Ok(0o227));
+        assert_eq!(parse("o=u", 0o227), Ok(0o222));
+        assert_eq!(parse("go-rwx+r", 0o22), Ok(0o33));
+        assert!(parse("u=rwq", 0o22).is_err());
+        assert!(parse("u", 0o22).is_err());
+    }
+}
@@ -2,10 +2,12 @@ use crate::expr::Argument;
 use crate::parser::tokenizer;
 use nix::sys::signal;
 use std::env;
-use std::io::{self, BufRead, Write};
+use std::io;
+use std::mem;
 use std::panic;
 use std::rc::Rc;
 use std::sync::{Arc, LazyLock, Mutex};
+mod editor;
 mod expr;
 mod parser;
 
@@ -22,9 +24,14 @@ pub extern "C" fn sigint_handler(_signum: i32) {
 }
 
 fn repl() {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
     let state = expr::State::new();
+    if let Some(name) = env::args().next() {
+        state.borrow_mut().shell_name = name;
+    }
+    unsafe {
+        env::set_var("OPTIND", "1");
+        env::set_var("PWD", &state.borrow().cwd);
+    }
 
     panic::set_hook(Box::new(|panic_info| {
         println!("one or more internal error occurred while running slush.");
@@ -48,8 +55,9 @@ fn repl() {
         .expect("Error setting signal handler for SIGINT");
     }
     if let Some(arg) = env::args().nth(1) {
-        let code_str = std::fs::read_to_string(arg).expect("Error reading file");
+        let code_str = std::fs::read_to_string(&arg).expect("Error reading file");
         let s = state.clone();
+        s.borrow_mut().shell_name = arg.clone();
         {
             let passed_args: Vec<String> = env::args().collect();
             let script_args = &mut s.borrow_mut().argstack;
@@ -61,25 +69,56 @@ fn repl() {
                     .collect::<Vec<Argument>>(),
             ));
         }
-        let mut parser = parser::Parser::new(s.clone());
-        parser.parse(&code_str);
-        if !parser.err.is_empty() {
-            println!("{}", parser.err);
-            return;
-        } else {
+        // One complete command at a time, so an alias defined on one line
+        // is there for the lines after it.
+        let mut text = String::new();
+        let mut first_line = 1;
+        let mut lines = code_str.split('\n').enumerate().peekable();
+        'script: while let Some((i, line)) = lines.next() {
+            if text.is_empty() {
+                first_line = i + 1;
+            } else {
+                text.push('\n');
+            }
+            text += line;
+            let mut parser = parser::Parser::new(s.clone());
+            parser.first_line = first_line;
+            parser.parse(&text);
+            if parser.incomplete && lines.peek().is_some() {
+                continue;
+            }
+            text.clear();
+            if !parser.err.is_empty() {
+                println!("{}", parser.err);
+                s.borrow_mut().prev_status = 2;
+                break 'script;
+            }
             for mut expr in parser.exprs {
                 let expr_status = expr.eval();
                 if let Ok(status) = expr_status {
                     s.borrow_mut().prev_status = status;
+                } else if s.borrow().expansion_failed {
+                    // a failed ${name?word} or `exec <file` ends the script, the
+                    // message is already out. The EXIT trap still runs on the way.
+                    s.borrow_mut().prev_status = 2;
+                    break 'script;
                 } else if let Err(err) = expr_status {
+                    // most often a redirect that couldn't be made
                     println!("{err}");
+                    s.borrow_mut().prev_status = 1;
                 }
             }
         }
-        std::process::exit(state.borrow().prev_status);
+        let status = state.borrow().prev_status;
+        expr::trap::exit(&state, status);
     } else {
         println!("Hello, Slush!");
         let s = state.clone();
+        let mut line_number = 0;
+        let mut editor = editor::Editor::new(state.clone());
+        let _ = state.borrow_mut().options.set("histexpand", true);
+        // the lines of a command that needs more of them, read with $PS2
+        let mut text = String::new();
         loop {
             if *PRUNE_JOBS.lock().unwrap() {
                 let mut jobs = state.borrow_mut();
@@ -87,14 +126,51 @@ fn repl() {
                     .retain(|job| job.child.try_wait().unwrap().is_some());
                 *PRUNE_JOBS.lock().unwrap() = false;
             }
-            print!("[{}] $ ", state.borrow().prev_status);
-            stdout.flush().expect("Error flushing to stdout");
-            let line = match stdin.lock().lines().next() {
-                Some(Ok(line)) => line,
-                _ => break,
+            let prompt = expr::prompt::expand(if text.is_empty() { "PS1" } else { "PS2" }, &state);
+            let line = match editor.read_line(&prompt) {
+                Ok(Some(line)) => line,
+                // Ctrl-C drops the whole command, not just this line of it
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    text.clear();
+                    continue;
+                }
+                _ => {
+                    if !text.is_empty() {
+                        eprintln!("slush: Syntax error: Unexpected end of file");
+                    }
+                    break;
+                }
+            };
+            let line = if state.borrow().options.is_set("histexpand") {
+                match editor::expand::expand(&line, &text, editor.history.entries()) {
+                    Ok(Some(expanded)) => {
+                        // show what is about to run
+                        eprintln!("{expanded}");
+                        expanded
+                    }
+                    Ok(None) => line,
+                    Err(e) => {
+                        eprintln!("slush: {e}");
+                        text.clear();
+                        continue;
+                    }
+                }
+            } else {
+                line
             };
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text += &line;
             let mut parser = parser::Parser::new(state.clone());
-            parser.parse(&line);
+            parser.first_line = line_number + 1;
+            parser.parse(&text);
+            if parser.incomplete {
+                continue;
+            }
+            let text = mem::take(&mut text);
+            editor.history.add(&text);
+            line_number += text.matches('\n').count() + 1;
             if !parser.err.is_empty() {
                 println!("{}", parser.err);
                 continue;
@@ -103,14 +179,22 @@ fn repl() {
                     let expr_status = expr.eval();
                     if let Ok(status) = expr_status {
                         s.borrow_mut().prev_status = status;
+                    } else if s.borrow().expansion_failed {
+                        // at the prompt only the rest of the line is dropped
+                        let mut state = s.borrow_mut();
+                        state.expansion_failed = false;
+                        state.prev_status = 2;
+                        break;
                     } else if let Err(err) = expr_status {
                         println!("{err}");
+                        s.borrow_mut().prev_status = 1;
                     }
                 }
             }
 
             s.borrow_mut().fg_jobs.clear();
         }
+        expr::trap::run_exit_trap(&state);
     }
 }
 
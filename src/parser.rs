@@ -1,8 +1,11 @@
 pub mod tokenizer;
+mod unfinished;
+use crate::expr::pattern::{CaseChange, ReplaceMode};
 use crate::expr::{
     AndIf, AndOrNode, Argument, AssignmentExpr, CommandExpr, CompoundList, ExpansionExpr, ForExpr,
-    FunctionExpr, FunctionStack, IfBranch, IfExpr, MergeExpr, NotExpr, OrIf, PipeLineExpr,
-    RedirectExpr, RedirectType, State, SubShellExpr, VariableLookup, WhileExpr,
+    FunctionExpr, FunctionStack, IfBranch, IfExpr, MergeExpr, NotExpr, OrIf, PatternExpr,
+    PipeLineExpr, RedirectExpr, RedirectType, State, SubShellExpr, TimeExpr, VariableLookup,
+    WhileExpr,
 };
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -13,6 +16,7 @@ use crate::tokenizer::{ShTokenType, Token, tokens};
 static ENDOFFILE: LazyLock<Token> = LazyLock::new(|| Token {
     lexeme: "".to_string(),
     token_type: ShTokenType::EndOfFile,
+    line: 0,
 });
 
 pub struct Parser {
@@ -21,6 +25,16 @@ pub struct Parser {
     loc: usize,
     pub err: String,
     state: Rc<RefCell<State>>,
+    // aliases being expanded right now and the token their text ends at,
+    // an alias isn't expanded again inside its own text.
+    active_aliases: Vec<(String, usize)>,
+    // the line number of the first line of text handed to parse, for
+    // $LINENO when the text doesn't start a file
+    pub first_line: usize,
+    // set when the text ran out in the middle of something, an open quote,
+    // an if without its fi, a trailing | or &&, rather than being wrong.
+    // More lines could still finish it, see unfinished.
+    pub incomplete: bool,
 }
 
 impl Parser {
@@ -31,11 +45,15 @@ impl Parser {
             loc: 0,
             err: "".to_string(),
             state,
+            active_aliases: Vec::new(),
+            first_line: 1,
+            incomplete: false,
         }
     }
 
     pub fn parse(&mut self, line: &str) {
         self.err = "".to_string();
+        self.incomplete = unfinished::unfinished(line);
         self.token = match tokens(line, false) {
             Ok(t) => t,
             Err(e) => {
@@ -43,8 +61,17 @@ impl Parser {
                 Vec::new()
             }
         };
+        for token in &mut self.token {
+            token.line += self.first_line - 1;
+        }
 
-        while !self.current_is(ShTokenType::EndOfFile) {
+        loop {
+            // blank lines and comments alone make no command
+            self.skip_whitespace_newlines();
+            if self.current_is(ShTokenType::EndOfFile) {
+                break;
+            }
+            let start = self.loc;
             match self.parse_andor_list() {
                 Ok(expr) => self.exprs.push(expr),
                 Err(strn) => {
@@ -52,6 +79,11 @@ impl Parser {
                 }
             };
             self.try_consume(ShTokenType::NewLine);
+            // a token nothing can start with, like a stray ')'
+            if self.loc == start {
+                self.err += &format!("Syntax error: Unexpected {:?}", self.current().lexeme);
+                break;
+            }
         }
     }
 
@@ -99,16 +131,17 @@ impl Parser {
         if self.try_consume(ShTokenType::Bang) {
             not = true;
         }
-        let mut left = AndOrNode::Pipeline(Box::new(self.parse_pipeline()?));
+        let mut left = self.parse_timed_pipeline()?;
         while self.current_is(ShTokenType::AndIf) || self.current_is(ShTokenType::OrIf) {
             if self.try_consume(ShTokenType::AndIf) {
-                let right = AndOrNode::Pipeline(Box::new(self.parse_pipeline()?));
+                self.skip_whitespace_newlines();
+                let right = self.parse_timed_pipeline()?;
                 left = AndOrNode::Andif(Box::new(AndIf { left, right }));
             }
             // these feels yucky - how do we get these two nearly identical blocks
             if self.try_consume(ShTokenType::OrIf) {
-                self.skip_whitespace();
-                let right = AndOrNode::Pipeline(Box::new(self.parse_pipeline()?));
+                self.skip_whitespace_newlines();
+                let right = self.parse_timed_pipeline()?;
                 left = AndOrNode::Orif(Box::new(OrIf { left, right }));
             }
         }
@@ -120,15 +153,41 @@ impl Parser {
         Ok(left)
     }
 
+    // a pipeline, optionally prefixed with `time [-p]`
+    fn parse_timed_pipeline(&mut self) -> Result<AndOrNode, String> {
+        self.skip_whitespace();
+        if !self.try_consume(ShTokenType::Time) {
+            return Ok(AndOrNode::Pipeline(Box::new(self.parse_pipeline()?)));
+        }
+        self.skip_whitespace();
+        let posix = self.current_is(ShTokenType::Name) && self.current().lexeme == "-p";
+        if posix {
+            self.next_token();
+        }
+        // `time` on its own times nothing and prints zeros
+        let pipeline = match self.current().token_type {
+            ShTokenType::NewLine
+            | ShTokenType::SemiColon
+            | ShTokenType::EndOfFile
+            | ShTokenType::AndIf
+            | ShTokenType::OrIf => PipeLineExpr {
+                pipeline: Vec::new(),
+                background: false,
+                state: self.state.clone(),
+                line: self.current().line,
+            },
+            _ => self.parse_pipeline()?,
+        };
+        Ok(AndOrNode::Time(Box::new(TimeExpr { pipeline, posix })))
+    }
+
     fn parse_pipeline(&mut self) -> Result<PipeLineExpr, String> {
         self.skip_whitespace();
+        let line = self.current().line;
         let mut pipeline: Vec<CompoundList> = Vec::new();
-        pipeline.push(match self.current().token_type {
-            ShTokenType::LeftBrace => CompoundList::CommandBlock(self.parse_block_command()?),
-            ShTokenType::If => CompoundList::Ifexpr(self.parse_if()?),
-            ShTokenType::While | ShTokenType::Until => CompoundList::Whileexpr(self.parse_while()?),
-            ShTokenType::For => CompoundList::Forexpr(self.parse_for()?),
-            _ => {
+        pipeline.push(match self.parse_compound()? {
+            Some(compound) => compound,
+            None => {
                 if let Some(function) = self.parse_function()? {
                     CompoundList::Functionexpr(function)
                 } else {
@@ -137,36 +196,50 @@ impl Parser {
             }
         });
         while self.try_consume(ShTokenType::Pipe) {
-            self.skip_whitespace();
-            let cmd = match self.current().token_type {
-                ShTokenType::LeftBrace => CompoundList::CommandBlock(self.parse_block_command()?),
-                ShTokenType::If => CompoundList::Ifexpr(self.parse_if()?),
-                ShTokenType::While | ShTokenType::Until => {
-                    CompoundList::Whileexpr(self.parse_while()?)
-                }
-                ShTokenType::For => CompoundList::Forexpr(self.parse_for()?),
-                _ => CompoundList::Commandexpr(self.parse_command()?),
+            // the next command may be on the next line
+            self.skip_whitespace_newlines();
+            let cmd = match self.parse_compound()? {
+                Some(compound) => compound,
+                None => CompoundList::Commandexpr(self.parse_command()?),
             };
             pipeline.push(cmd);
         }
 
-        let mut file_redirect = None;
-        self.skip_whitespace();
-        if !self.current_is(ShTokenType::EndOfFile) && !self.current_is(ShTokenType::NewLine) {
-            file_redirect = self.parse_redirect()?;
-        }
         let background = self.parse_control();
 
         Ok(PipeLineExpr {
             pipeline,
-            capture_out: None,
-            file_redirect,
             background,
-            pipe_in: None,
             state: self.state.clone(),
+            line,
         })
     }
 
+    // an if, while, until, for or { } block along with any redirects after it,
+    // None when the pipeline stage is something else
+    fn parse_compound(&mut self) -> Result<Option<CompoundList>, String> {
+        let compound = match self.current().token_type {
+            ShTokenType::LeftBrace => CompoundList::CommandBlock(self.parse_block_command()?),
+            ShTokenType::If => CompoundList::Ifexpr(self.parse_if()?),
+            ShTokenType::While | ShTokenType::Until => CompoundList::Whileexpr(self.parse_while()?),
+            ShTokenType::For => CompoundList::Forexpr(self.parse_for()?),
+            _ => return Ok(None),
+        };
+        let mut redirects = Vec::new();
+        while let Some(redirect) = self.parse_redirect()? {
+            redirects.push(redirect);
+        }
+        self.skip_whitespace();
+        if redirects.is_empty() {
+            Ok(Some(compound))
+        } else {
+            Ok(Some(CompoundList::Redirected(
+                Box::new(compound),
+                redirects,
+            )))
+        }
+    }
+
     // parse_if builds out entire if/elif/else chain.
     fn parse_if(&mut self) -> Result<IfExpr, String> {
         if !self.try_consume(ShTokenType::If) {
@@ -323,8 +396,18 @@ impl Parser {
     }
 
     fn parse_command(&mut self) -> Result<CommandExpr, String> {
+        self.skip_whitespace();
+        let mut check_next_alias = self.expand_alias()?;
         let assignment = self.parse_assignment()?;
+        if assignment.is_some() {
+            self.skip_whitespace();
+            check_next_alias = self.expand_alias()?;
+        }
         let mut err: String = "".to_string();
+        let mut redirects: Vec<RedirectExpr> = Vec::new();
+        while let Some(redirect) = self.parse_redirect()? {
+            redirects.push(redirect);
+        }
         self.skip_whitespace();
         let command_name = match self.parse_argument()? {
             Some(a) => a,
@@ -337,28 +420,78 @@ impl Parser {
             }
         };
 
-        if !err.is_empty() && assignment.is_some() {
+        if !err.is_empty() && (assignment.is_some() || !redirects.is_empty()) {
             return Ok(CommandExpr {
                 command: command_name,
                 arguments: Rc::new(Vec::new()),
                 assignment,
+                redirects,
             });
         }
         let mut command = CommandExpr {
             command: command_name,
             arguments: Rc::new(Vec::new()),
             assignment,
+            redirects,
         };
 
-        self.skip_whitespace();
-        while let Some(argument) = self.parse_argument()? {
-            Rc::get_mut(&mut command.arguments).unwrap().push(argument);
+        // an alias ending in a blank means the word after it is also checked
+        if check_next_alias {
+            self.skip_whitespace();
+            self.expand_alias()?;
+        }
+
+        // redirects can show up anywhere between the arguments
+        loop {
+            if let Some(redirect) = self.parse_redirect()? {
+                command.redirects.push(redirect);
+            } else if let Some(argument) = self.parse_argument()? {
+                Rc::get_mut(&mut command.arguments).unwrap().push(argument);
+            } else {
+                break;
+            }
             self.skip_whitespace();
         }
         self.try_consume(ShTokenType::SemiColon);
         Ok(command)
     }
 
+    // Aliases are substituted by splicing the tokens of their value into the
+    // token stream in place of the word. Returns true when the value ends in a
+    // blank, in which case the following word should be checked as well.
+    fn expand_alias(&mut self) -> Result<bool, String> {
+        let mut trailing_blank = false;
+        while self.current_is(ShTokenType::Name) {
+            let name = self.current().lexeme.clone();
+            let loc = self.loc;
+            self.active_aliases.retain(|(_, end)| *end > loc);
+            if self
+                .active_aliases
+                .iter()
+                .any(|(active, _)| *active == name)
+            {
+                break;
+            }
+            let Some(value) = self.state.borrow().aliases.get(&name).cloned() else {
+                break;
+            };
+            let line = self.token[loc].line;
+            let alias_tokens: Vec<Token> = tokens(&value, false)?
+                .into_iter()
+                .map(|token| Token { line, ..token })
+                .collect();
+            let len = alias_tokens.len();
+            for (_, end) in self.active_aliases.iter_mut() {
+                *end = *end + len - 1;
+            }
+            self.token.splice(loc..loc + 1, alias_tokens);
+            self.active_aliases.push((name, loc + len));
+            trailing_blank = value.ends_with([' ', '\t']);
+            self.skip_whitespace();
+        }
+        Ok(trailing_blank)
+    }
+
     // assignment expressions are optional at the beginning, it can be difficult
     // to tell if the assignment is a TRUE assignment until you get to an '=' sign
     // for example:
@@ -380,7 +513,11 @@ impl Parser {
                 self.loc = current_location;
                 return Ok(None);
             }
-            val = self.parse_argument()?;
+            // 'VAR=' on its own sets VAR to the empty string
+            val = Some(
+                self.parse_argument()?
+                    .unwrap_or_else(|| Argument::Name(String::default())),
+            );
         }
         if let Some(argtype) = val {
             return Ok(Some(AssignmentExpr {
@@ -400,7 +537,8 @@ impl Parser {
 
     fn parse_expansion(&mut self) -> Result<ExpansionExpr, String> {
         self.next_token();
-        if self.current_is(ShTokenType::Pound) {
+        if self.current_is(ShTokenType::Pound) && self.peek().token_type != ShTokenType::RightBrace
+        {
             self.next_token();
             if !self.current_is(ShTokenType::Name) {
                 return Err(String::from("Expected a name after '#'"));
@@ -409,25 +547,73 @@ impl Parser {
             self.next_token();
             self.consume(ShTokenType::RightBrace)?;
             return Ok(ExpansionExpr::StringLengthExpansion(varname));
-        } else if self.current_is(ShTokenType::Name) {
+        } else if matches!(
+            self.current().token_type,
+            ShTokenType::Name
+                | ShTokenType::Pound
+                | ShTokenType::AtSign
+                | ShTokenType::Star
+                | ShTokenType::DollarSign
+                | ShTokenType::Bang
+        ) {
             // we are doing some type expansion thiny
-            let name = self.current().lexeme.clone();
-            self.next_token();
-            if self.current_is(ShTokenType::UseDefault) {
-                self.next_token();
-                let default = self.collect_until(ShTokenType::RightBrace)?;
-                return Ok(ExpansionExpr::ParameterSubstitute(name, default));
-            } else if self.current_is(ShTokenType::AssignDefault) {
-                self.next_token();
-                let default = self.collect_until(ShTokenType::RightBrace)?;
-                return Ok(ExpansionExpr::ParameterAssign(name, default));
-            } else if self.current_is(ShTokenType::ErrorOn) {
-                self.next_token();
-                let default = self.collect_until(ShTokenType::RightBrace)?;
-                return Ok(ExpansionExpr::ParameterError(name, default));
-            } else if self.current_is(ShTokenType::UseNullOrDefault) {
-                self.next_token();
-                return Ok(ExpansionExpr::ParameterExpansion(name));
+            let name = self.parse_expansion_name();
+            if self.try_consume(ShTokenType::Pound) {
+                let longest = self.try_consume(ShTokenType::Pound);
+                let pattern = self.parse_expansion_pattern()?;
+                return Ok(if longest {
+                    ExpansionExpr::RemoveLongestPrefix(name, pattern)
+                } else {
+                    ExpansionExpr::RemoveShortestPrefix(name, pattern)
+                });
+            } else if let Some(operator) = self.strip_operator(&["%%", "%"]) {
+                let pattern = self.parse_expansion_pattern()?;
+                return Ok(if operator == "%%" {
+                    ExpansionExpr::RemoveLongestSuffix(name, pattern)
+                } else {
+                    ExpansionExpr::RemoveShortestSuffix(name, pattern)
+                });
+            } else if let Some((operator, colon)) = self.parse_word_operator() {
+                let word = self.parse_expansion_word()?;
+                return Ok(match operator {
+                    '-' => ExpansionExpr::ParameterSubstitute(name, word, colon),
+                    '=' => ExpansionExpr::ParameterAssign(name, word, colon),
+                    '?' => ExpansionExpr::ParameterError(name, word, colon),
+                    _ => ExpansionExpr::ParameterAlternate(name, word, colon),
+                });
+            } else if self.strip_operator(&[":"]).is_some() {
+                return Ok(ExpansionExpr::Substring(name, self.parse_expansion_word()?));
+            } else if let Some(operator) = self.strip_operator(&["//", "/%", "/"]) {
+                let mode = match operator {
+                    "//" => ReplaceMode::All,
+                    "/%" => ReplaceMode::Suffix,
+                    _ if self.try_consume(ShTokenType::Pound) => ReplaceMode::Prefix,
+                    _ => ReplaceMode::First,
+                };
+                let (parts, more) = self.parse_expansion_parts(Some('/'))?;
+                let with = if more {
+                    self.parse_expansion_word()?
+                } else {
+                    Box::new(Argument::Name(String::new()))
+                };
+                return Ok(ExpansionExpr::Replace(
+                    name,
+                    PatternExpr { parts },
+                    with,
+                    mode,
+                ));
+            } else if let Some(operator) = self.strip_operator(&["^^", "^", ",,", ","]) {
+                let change = match operator {
+                    "^^" => CaseChange::UpperAll,
+                    "^" => CaseChange::UpperFirst,
+                    ",," => CaseChange::LowerAll,
+                    _ => CaseChange::LowerFirst,
+                };
+                return Ok(ExpansionExpr::ChangeCase(
+                    name,
+                    self.parse_expansion_pattern()?,
+                    change,
+                ));
             } else {
                 self.consume(ShTokenType::RightBrace)?;
                 return Ok(ExpansionExpr::ParameterExpansion(name));
@@ -438,24 +624,194 @@ impl Parser {
         Err(String::from("Error parsing expansion"))
     }
 
-    fn parse_redirect(&mut self) -> Result<Option<RedirectExpr>, String> {
-        self.skip_whitespace();
-        let mut file_descriptor = 1;
-        let mode = if self.try_consume(ShTokenType::RedirectOut) {
-            RedirectType::Out
-        } else if self.try_consume(ShTokenType::AppendOut) {
-            RedirectType::OutAppend
-        } else if self.try_consume(ShTokenType::RedirectIn) {
-            file_descriptor = 0;
-            RedirectType::In
+    // The name at the start of an expansion. A name token can carry whatever
+    // follows the name with it ('${f%.txt}' is one token), that part is left
+    // in place for the operator.
+    fn parse_expansion_name(&mut self) -> String {
+        let lexeme = self.current().lexeme.clone();
+        let len = if !self.current_is(ShTokenType::Name) {
+            lexeme.len()
+        } else if lexeme.starts_with(|c: char| c.is_ascii_digit()) {
+            lexeme
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(lexeme.len())
         } else {
-            if self.current_is(ShTokenType::EndOfFile) {
-                self.rewind(0);
-            } else {
-                self.rewind(1);
+            lexeme
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(lexeme.len())
+        };
+        if len == 0 || len == lexeme.len() {
+            self.next_token();
+            return lexeme;
+        }
+        self.token[self.loc].lexeme = lexeme[len..].to_string();
+        lexeme[..len].to_string()
+    }
+
+    // Operators made of chars that aren't delimiters, like the '%' in
+    // '${f%.txt}', arrive stuck to the front of a name token. This takes the
+    // first of operators found there off the token.
+    fn strip_operator(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        if !self.current_is(ShTokenType::Name) {
+            return None;
+        }
+        let lexeme = self.current().lexeme.clone();
+        let operator = *operators.iter().find(|op| lexeme.starts_with(**op))?;
+        if lexeme.len() == operator.len() {
+            self.next_token();
+        } else {
+            self.token[self.loc].lexeme = lexeme[operator.len()..].to_string();
+        }
+        Some(operator)
+    }
+
+    // Everything up to the closing brace of an expansion, each piece with
+    // whether it was quoted. Spaces are kept. With a stop char the parts can
+    // also end at the first unquoted one, the bool says if that happened.
+    fn parse_expansion_parts(
+        &mut self,
+        stop: Option<char>,
+    ) -> Result<(Vec<(Argument, bool)>, bool), String> {
+        let mut parts = Vec::new();
+        loop {
+            let part = match self.current().token_type {
+                ShTokenType::RightBrace => {
+                    self.next_token();
+                    return Ok((parts, false));
+                }
+                ShTokenType::EndOfFile => {
+                    return Err(
+                        "Syntax Error: Unexpected end of file, no matching 'RightBrace'"
+                            .to_string(),
+                    );
+                }
+                ShTokenType::DollarSign => {
+                    self.next_token();
+                    match self.parse_variable_name()? {
+                        Some(argument) => (argument, false),
+                        None => continue,
+                    }
+                }
+                ShTokenType::BackTickStr => (
+                    Argument::SubShell(SubShellExpr {
+                        shell: self.consume_current().as_original(),
+                    }),
+                    false,
+                ),
+                ShTokenType::DoubleQuoteStr => (
+                    Argument::QuoteString(self.consume_current().lexeme.clone()),
+                    true,
+                ),
+                ShTokenType::SingleQuoteStr => {
+                    (Argument::Name(self.consume_current().lexeme.clone()), true)
+                }
+                ShTokenType::Name
+                    if let Some(stop) = stop
+                        && let Some(at) = self.current().lexeme.find(stop) =>
+                {
+                    let lexeme = self.current().lexeme.clone();
+                    if at > 0 {
+                        parts.push((Argument::Name(lexeme[..at].to_string()), false));
+                    }
+                    if at + 1 == lexeme.len() {
+                        self.next_token();
+                    } else {
+                        self.token[self.loc].lexeme = lexeme[at + 1..].to_string();
+                    }
+                    return Ok((parts, true));
+                }
+                _ => (Argument::Name(self.consume_current().lexeme.clone()), false),
+            };
+            parts.push(part);
+        }
+    }
+
+    // The operator of ${name-word} and the other forms with a word, as one of
+    // '-', '=', '?' or '+' along with whether there was a colon in front.
+    fn parse_word_operator(&mut self) -> Option<(char, bool)> {
+        let operator = match self.current().token_type {
+            ShTokenType::UseDefault => ('-', true),
+            ShTokenType::AssignDefault => ('=', true),
+            ShTokenType::ErrorOn => ('?', true),
+            ShTokenType::UseNullOrDefault => ('+', true),
+            ShTokenType::Equal => ('=', false),
+            // the others aren't tokens of their own and come stuck to the word
+            ShTokenType::Name => {
+                let operator = self.strip_operator(&["-", "?", "+"])?;
+                return Some((operator.chars().next()?, false));
             }
-            return Ok(None);
+            _ => return None,
+        };
+        self.next_token();
+        Some(operator)
+    }
+
+    // the word of ${name:-word} and friends, expanded only when it is used
+    fn parse_expansion_word(&mut self) -> Result<Box<Argument>, String> {
+        let word = self
+            .parse_expansion_parts(None)?
+            .0
+            .into_iter()
+            .map(|(part, _)| part)
+            .reduce(|left, right| {
+                Argument::Merge(MergeExpr {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                })
+            });
+        Ok(Box::new(word.unwrap_or(Argument::Name(String::new()))))
+    }
+
+    fn parse_expansion_pattern(&mut self) -> Result<PatternExpr, String> {
+        Ok(PatternExpr {
+            parts: self.parse_expansion_parts(None)?.0,
+        })
+    }
+
+    fn is_redirect(token: ShTokenType) -> bool {
+        matches!(
+            token,
+            ShTokenType::RedirectOut
+                | ShTokenType::AppendOut
+                | ShTokenType::RedirectIn
+                | ShTokenType::DuplicateOut
+                | ShTokenType::DuplicateIn
+        )
+    }
+
+    // A redirect may be prefixed by the file descriptor it applies to, as in
+    // '2>/dev/null', but only when the number is directly against the operator
+    // otherwise its just another argument ('echo 2 > file').
+    fn parse_redirect(&mut self) -> Result<Option<RedirectExpr>, String> {
+        self.skip_whitespace();
+        let mut file_descriptor = None;
+        if self.current_is(ShTokenType::Name)
+            && self.current().lexeme.chars().all(|c| c.is_ascii_digit())
+            && Self::is_redirect(self.peek().token_type)
+        {
+            file_descriptor = match self.consume_current().lexeme.parse::<u32>() {
+                Ok(fd) => Some(fd),
+                Err(_) => {
+                    return Err(format!(
+                        "Syntax error: Bad file descriptor '{}'",
+                        self.prev().lexeme
+                    ));
+                }
+            };
+        }
+        let mode = match self.current().token_type {
+            ShTokenType::RedirectOut => RedirectType::Out,
+            ShTokenType::AppendOut => RedirectType::OutAppend,
+            ShTokenType::RedirectIn => RedirectType::In,
+            ShTokenType::DuplicateOut => RedirectType::OutDup,
+            ShTokenType::DuplicateIn => RedirectType::InDup,
+            _ => return Ok(None),
         };
+        self.next_token();
+        let file_descriptor = file_descriptor.unwrap_or(match mode {
+            RedirectType::In | RedirectType::InDup => 0,
+            _ => 1,
+        });
         self.skip_whitespace();
         let file = match self.parse_argument()? {
             Some(a) => a,
@@ -505,9 +861,31 @@ impl Parser {
 
     fn parse_variable_name(&mut self) -> Result<Option<Argument>, String> {
         match self.current().token_type {
-            ShTokenType::Name => Ok(Some(Argument::Variable(VariableLookup {
-                name: self.consume_current().lexeme.clone(),
-            }))),
+            ShTokenType::Name => {
+                // the tokenizer doesn't know where a name ends, so '$HOME/bin' arrives
+                // as one token. Positional parameters are a single digit ('$10' is '${1}0').
+                let lexeme = self.current().lexeme.clone();
+                let len =
+                    if lexeme.starts_with(|c: char| c.is_ascii_digit() || c == '?' || c == '-') {
+                        1
+                    } else {
+                        lexeme
+                            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                            .unwrap_or(lexeme.len())
+                    };
+                if len == 0 {
+                    // not a parameter at all so the dollar sign is just a dollar sign
+                    return Ok(Some(Argument::Name(String::from("$"))));
+                }
+                if len < lexeme.len() {
+                    self.token[self.loc].lexeme = lexeme[len..].to_string();
+                } else {
+                    self.next_token();
+                }
+                Ok(Some(Argument::Variable(VariableLookup {
+                    name: lexeme[..len].to_string(),
+                })))
+            }
             ShTokenType::LeftParen => Ok(Some(Argument::SubShell(SubShellExpr {
                 shell: self.collect_matching(ShTokenType::LeftParen, ShTokenType::RightParen)?,
             }))),
@@ -549,6 +927,8 @@ impl Parser {
             | ShTokenType::RedirectOut
             | ShTokenType::AppendOut
             | ShTokenType::RedirectIn
+            | ShTokenType::DuplicateOut
+            | ShTokenType::DuplicateIn
             | ShTokenType::Control
             | ShTokenType::RightParen
             | ShTokenType::LeftParen
@@ -583,6 +963,13 @@ impl Parser {
             ShTokenType::Name => Ok(Some(Argument::Name(self.consume_current().lexeme.clone()))),
             ShTokenType::DollarSign => {
                 self.consume(ShTokenType::DollarSign)?;
+                // "cost: $ 5" and "$" keep the dollar sign as it is
+                if self.current_is(ShTokenType::WhiteSpace)
+                    || self.current_is(ShTokenType::NewLine)
+                    || self.current_is(ShTokenType::EndOfFile)
+                {
+                    return Ok(Some(Argument::Name(String::from("$"))));
+                }
                 self.parse_variable_name()
             }
             ShTokenType::BackTickStr => Ok(Some(Argument::SubShell(SubShellExpr {
@@ -634,20 +1021,6 @@ impl Parser {
         Ok(ret)
     }
 
-    fn collect_until(&mut self, stop: ShTokenType) -> Result<String, String> {
-        let mut ret: String = String::new();
-        while !self.try_consume(stop) {
-            if self.current_is(ShTokenType::EndOfFile) {
-                return Err(format!(
-                    "Syntax Error: Unexpected end of file, no matching '{stop:?}'"
-                ));
-            }
-            ret.push_str(&self.current().lexeme);
-            self.next_token();
-        }
-        Ok(ret)
-    }
-
     fn current_is(&self, check: ShTokenType) -> bool {
         self.current().token_type == check
     }
@@ -690,6 +1063,14 @@ impl Parser {
         self.loc += 1;
     }
 
+    fn peek(&self) -> &Token {
+        if self.loc + 1 < self.token.len() {
+            &self.token[self.loc + 1]
+        } else {
+            &ENDOFFILE
+        }
+    }
+
     fn current(&self) -> &Token {
         if self.loc < self.token.len() {
             &self.token[self.loc]
@@ -707,6 +1088,8 @@ impl Parser {
     }
 }
 
+#[cfg(test)]
+#[allow(clippy::needless_borrow)]
 mod test {
     #[allow(unused_imports)]
     use super::*;
@@ -728,12 +1111,11 @@ mod test {
                 ])
                 .into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         for (i, expr) in golden_set.into_iter().enumerate() {
@@ -752,12 +1134,11 @@ mod test {
                 command: Argument::Name("ls".to_string()),
                 arguments: Vec::new().into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         for (i, expr) in golden_set.into_iter().enumerate() {
@@ -776,18 +1157,18 @@ mod test {
                     command: Argument::Name("ls".to_string()),
                     arguments: Vec::new().into(),
                     assignment: None,
+                    redirects: Vec::new(),
                 }),
                 CompoundList::Commandexpr(CommandExpr {
                     command: Argument::Name("wc".to_string()),
                     arguments: Vec::new().into(),
                     assignment: None,
+                    redirects: Vec::new(),
                 }),
             ]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         for (i, expr) in golden_set.into_iter().enumerate() {
@@ -833,12 +1214,11 @@ mod test {
                 })])
                 .into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         for (i, expr) in golden_set.into_iter().enumerate() {
@@ -868,24 +1248,22 @@ mod test {
                     command: Argument::Name("echo".to_string()),
                     arguments: Vec::from([Argument::Name("hello world".to_string())]).into(),
                     assignment: None,
+                    redirects: Vec::new(),
                 })]),
-                capture_out: None,
-                file_redirect: None,
                 background: false,
-                pipe_in: None,
                 state: expr::State::new(),
+                line: 1,
             })),
             AndOrNode::Pipeline(Box::new(PipeLineExpr {
                 pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                     command: Argument::Name("echo".to_string()),
                     arguments: Vec::from([Argument::Name("goodbye world".to_string())]).into(),
                     assignment: None,
+                    redirects: Vec::new(),
                 })]),
-                capture_out: None,
-                file_redirect: None,
                 background: false,
-                pipe_in: None,
                 state: expr::State::new(),
+                line: 2,
             })),
         ]);
         parser.parse(&line);
@@ -906,24 +1284,22 @@ mod test {
                     command: Argument::Name("ls".to_string()),
                     arguments: Vec::new().into(),
                     assignment: None,
+                    redirects: Vec::new(),
                 })]),
-                capture_out: None,
-                file_redirect: None,
                 background: false,
-                pipe_in: None,
                 state: expr::State::new(),
+                line: 1,
             })),
             right: AndOrNode::Pipeline(Box::new(PipeLineExpr {
                 pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                     command: Argument::Name("pwd".to_string()),
                     arguments: Vec::new().into(),
                     assignment: None,
+                    redirects: Vec::new(),
                 })]),
-                capture_out: None,
-                file_redirect: None,
                 background: false,
-                pipe_in: None,
                 state: expr::State::new(),
+                line: 1,
             })),
         }))]);
         parser.parse(&line);
@@ -946,12 +1322,11 @@ mod test {
                 })])
                 .into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:?}", parser.exprs);
@@ -973,32 +1348,28 @@ mod test {
                         command: Argument::Name("true".to_string()),
                         arguments: Vec::new().into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 1,
                 },
                 if_branch: Vec::from([PipeLineExpr {
                     pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                         command: Argument::Name("echo".to_string()),
                         arguments: Vec::from([Argument::Name("hello world".to_string())]).into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 1,
                 }]),
                 else_branch: None,
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         assert!(parser.err.is_empty());
@@ -1023,12 +1394,11 @@ mod test {
                 })])
                 .into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         assert!(parser.err.is_empty());
@@ -1053,12 +1423,11 @@ mod test {
                 })])
                 .into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         assert!(parser.err.is_empty());
@@ -1083,12 +1452,11 @@ mod test {
                 })])
                 .into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:?}", parser.exprs);
@@ -1129,43 +1497,38 @@ mod test {
                         command: Argument::Name("true".to_string()),
                         arguments: Vec::new().into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 1,
                 },
                 if_branch: Vec::from([PipeLineExpr {
                     pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                         command: Argument::Name("echo".to_string()),
                         arguments: Vec::from([Argument::Name("hello world".to_string())]).into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 2,
                 }]),
                 else_branch: Some(IfBranch::Else(Vec::from([PipeLineExpr {
                     pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                         command: Argument::Name("echo".to_string()),
                         arguments: Vec::from([Argument::Name("goodbye world".to_string())]).into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 3,
                 }]))),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:#?}", parser.exprs);
@@ -1190,12 +1553,11 @@ mod test {
                 ])
                 .into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:#?}", parser.exprs);
@@ -1218,24 +1580,22 @@ mod test {
                         command: Argument::Name("true".to_string()),
                         arguments: Vec::new().into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 1,
                 },
                 if_branch: Vec::from([PipeLineExpr {
                     pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                         command: Argument::Name("exit".to_string()),
                         arguments: Vec::from([Argument::Name("1".to_string())]).into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 2,
                 }]),
                 else_branch: Some(expr::IfBranch::Elif(Box::new(IfExpr {
                     condition: PipeLineExpr {
@@ -1243,44 +1603,39 @@ mod test {
                             command: Argument::Name("false".to_string()),
                             arguments: Vec::new().into(),
                             assignment: None,
+                            redirects: Vec::new(),
                         })]),
-                        capture_out: None,
-                        file_redirect: None,
                         background: false,
-                        pipe_in: None,
                         state: expr::State::new(),
+                        line: 3,
                     },
                     if_branch: Vec::from([PipeLineExpr {
                         pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                             command: Argument::Name("exit".to_string()),
                             arguments: Vec::from([Argument::Name("2".to_string())]).into(),
                             assignment: None,
+                            redirects: Vec::new(),
                         })]),
-                        capture_out: None,
-                        file_redirect: None,
                         background: false,
-                        pipe_in: None,
                         state: expr::State::new(),
+                        line: 4,
                     }]),
                     else_branch: Some(IfBranch::Else(Vec::from([PipeLineExpr {
                         pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                             command: Argument::Name("exit".to_string()),
                             arguments: Vec::from([Argument::Name("3".to_string())]).into(),
                             assignment: None,
+                            redirects: Vec::new(),
                         })]),
-                        capture_out: None,
-                        file_redirect: None,
                         background: false,
-                        pipe_in: None,
                         state: expr::State::new(),
+                        line: 6,
                     }]))),
                 }))),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("---- Parser Tree ----");
@@ -1306,24 +1661,22 @@ mod test {
                         command: Argument::Name("true".to_string()),
                         arguments: Vec::new().into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 1,
                 },
                 if_branch: Vec::from([PipeLineExpr {
                     pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                         command: Argument::Name("exit".to_string()),
                         arguments: Vec::from([Argument::Name("1".to_string())]).into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 2,
                 }]),
                 else_branch: Some(IfBranch::Elif(Box::new(IfExpr {
                     condition: PipeLineExpr {
@@ -1331,33 +1684,29 @@ mod test {
                             command: Argument::Name("false".to_string()),
                             arguments: Vec::new().into(),
                             assignment: None,
+                            redirects: Vec::new(),
                         })]),
-                        capture_out: None,
-                        file_redirect: None,
                         background: false,
-                        pipe_in: None,
                         state: expr::State::new(),
+                        line: 3,
                     },
                     if_branch: Vec::from([PipeLineExpr {
                         pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                             command: Argument::Name("exit".to_string()),
                             arguments: Vec::from([Argument::Name("2".to_string())]).into(),
                             assignment: None,
+                            redirects: Vec::new(),
                         })]),
-                        capture_out: None,
-                        file_redirect: None,
                         background: false,
-                        pipe_in: None,
                         state: expr::State::new(),
+                        line: 4,
                     }]),
                     else_branch: None,
                 }))),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("---- Parser Tree ----");
@@ -1383,31 +1732,27 @@ mod test {
                         command: Argument::Name("true".to_string()),
                         arguments: Vec::new().into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 1,
                 })),
                 body: Vec::from([PipeLineExpr {
                     pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                         command: Argument::Name("echo".to_string()),
                         arguments: Vec::from([Argument::Name("hello world".to_string())]).into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 2,
                 }]),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:#?}", parser.exprs);
@@ -1430,31 +1775,27 @@ mod test {
                         command: Argument::Name("true".to_string()),
                         arguments: Vec::new().into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 1,
                 })),
                 body: Vec::from([PipeLineExpr {
                     pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                         command: Argument::Name("echo".to_string()),
                         arguments: Vec::from([Argument::Name("hello world".to_string())]).into(),
                         assignment: None,
+                        redirects: Vec::new(),
                     })]),
-                    capture_out: None,
-                    file_redirect: None,
                     background: false,
-                    pipe_in: None,
                     state: expr::State::new(),
+                    line: 3,
                 }]),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:#?}", parser.exprs);
@@ -1476,24 +1817,22 @@ mod test {
                     command: Argument::Name("echo".to_string()),
                     arguments: Vec::from([Argument::Name("hello world".to_string())]).into(),
                     assignment: None,
+                    redirects: Vec::new(),
                 })]),
-                capture_out: None,
-                file_redirect: None,
                 background: false,
-                pipe_in: None,
                 state: expr::State::new(),
+                line: 1,
             })),
             AndOrNode::Pipeline(Box::new(PipeLineExpr {
                 pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
                     command: Argument::Name("echo".to_string()),
                     arguments: Vec::from([Argument::Name("goodbye world".to_string())]).into(),
                     assignment: None,
+                    redirects: Vec::new(),
                 })]),
-                capture_out: None,
-                file_redirect: None,
                 background: false,
-                pipe_in: None,
                 state: expr::State::new(),
+                line: 1,
             })),
         ]);
         parser.parse(&line);
@@ -1521,12 +1860,11 @@ mod test {
                 ))])
                 .into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:#?}", parser.exprs);
@@ -1554,12 +1892,11 @@ mod test {
                 })])
                 .into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:#?}", parser.exprs);
@@ -1588,12 +1925,11 @@ mod test {
                 })])
                 .into(),
                 assignment: None,
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:#?}", parser.exprs);
@@ -1620,12 +1956,11 @@ mod test {
                     key: "X".to_string(),
                     val: Argument::Name("1".to_string()),
                 }),
+                redirects: Vec::new(),
             })]),
-            capture_out: None,
-            file_redirect: None,
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:#?}", parser.exprs);
@@ -1646,16 +1981,15 @@ mod test {
                 command: Argument::Name("ls".to_string()),
                 arguments: Vec::new().into(),
                 assignment: None,
+                redirects: Vec::from([RedirectExpr {
+                    file: expr::Argument::Name("/tmp/file".to_string()),
+                    mode: expr::RedirectType::Out,
+                    file_descriptor: 1,
+                }]),
             })]),
-            capture_out: None,
-            file_redirect: Some(RedirectExpr {
-                file: expr::Argument::Name("/tmp/file".to_string()),
-                mode: expr::RedirectType::Out,
-                file_descriptor: 1,
-            }),
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:#?}", parser.exprs);
@@ -1677,16 +2011,15 @@ mod test {
                 command: Argument::Name("ls".to_string()),
                 arguments: Vec::new().into(),
                 assignment: None,
+                redirects: Vec::from([RedirectExpr {
+                    file: expr::Argument::Name("/tmp/file".to_string()),
+                    mode: expr::RedirectType::OutAppend,
+                    file_descriptor: 1,
+                }]),
             })]),
-            capture_out: None,
-            file_redirect: Some(RedirectExpr {
-                file: expr::Argument::Name("/tmp/file".to_string()),
-                mode: expr::RedirectType::OutAppend,
-                file_descriptor: 1,
-            }),
             background: false,
-            pipe_in: None,
             state: expr::State::new(),
+            line: 1,
         }))]);
         parser.parse(&line);
         println!("{:#?}", parser.exprs);
@@ -1697,4 +2030,459 @@ mod test {
             assert!(parser.exprs[i].eq(&expr));
         }
     }
+
+    #[test]
+    fn test_fd_redirects() {
+        let line = "exec 3>log 2>&1 echo 2 >&-";
+        let state = expr::State::new();
+        let mut parser = Parser::new(state);
+        let golden_set = Vec::from([AndOrNode::Pipeline(Box::new(PipeLineExpr {
+            pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
+                command: Argument::Name("exec".to_string()),
+                arguments: Vec::from([
+                    Argument::Name("echo".to_string()),
+                    Argument::Name("2".to_string()),
+                ])
+                .into(),
+                assignment: None,
+                redirects: Vec::from([
+                    RedirectExpr {
+                        file: expr::Argument::Name("log".to_string()),
+                        mode: expr::RedirectType::Out,
+                        file_descriptor: 3,
+                    },
+                    RedirectExpr {
+                        file: expr::Argument::Name("1".to_string()),
+                        mode: expr::RedirectType::OutDup,
+                        file_descriptor: 2,
+                    },
+                    RedirectExpr {
+                        file: expr::Argument::Name("-".to_string()),
+                        mode: expr::RedirectType::OutDup,
+                        file_descriptor: 1,
+                    },
+                ]),
+            })]),
+            background: false,
+            state: expr::State::new(),
+            line: 1,
+        }))]);
+        parser.parse(line);
+        println!("{:#?}", parser.exprs);
+        assert!(parser.err.is_empty());
+        for (i, expr) in golden_set.into_iter().enumerate() {
+            assert!(parser.exprs[i].eq(&expr));
+        }
+    }
+
+    #[test]
+    fn test_redirect_before_command() {
+        let line = "<in cat";
+        let state = expr::State::new();
+        let mut parser = Parser::new(state);
+        parser.parse(line);
+        assert!(parser.err.is_empty());
+        let AndOrNode::Pipeline(pl) = &parser.exprs[0] else {
+            panic!("expected a pipeline");
+        };
+        let CompoundList::Commandexpr(cmd) = &pl.pipeline[0] else {
+            panic!("expected a command");
+        };
+        assert_eq!(cmd.command, Argument::Name("cat".to_string()));
+        assert_eq!(cmd.redirects[0].mode, expr::RedirectType::In);
+        assert_eq!(cmd.redirects[0].file_descriptor, 0);
+    }
+
+    #[test]
+    fn test_variable_name_boundaries() {
+        let line = "echo $10 $HOME/bin";
+        let state = expr::State::new();
+        let mut parser = Parser::new(state);
+        let golden_set = Vec::from([AndOrNode::Pipeline(Box::new(PipeLineExpr {
+            pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
+                command: Argument::Name("echo".to_string()),
+                arguments: Vec::from([
+                    Argument::Merge(MergeExpr {
+                        left: Box::new(Argument::Variable(VariableLookup {
+                            name: "1".to_string(),
+                        })),
+                        right: Box::new(Argument::Name("0".to_string())),
+                    }),
+                    Argument::Merge(MergeExpr {
+                        left: Box::new(Argument::Variable(VariableLookup {
+                            name: "HOME".to_string(),
+                        })),
+                        right: Box::new(Argument::Name("/bin".to_string())),
+                    }),
+                ])
+                .into(),
+                assignment: None,
+                redirects: Vec::new(),
+            })]),
+            background: false,
+            state: expr::State::new(),
+            line: 1,
+        }))]);
+        parser.parse(line);
+        println!("{:#?}", parser.exprs);
+        assert!(parser.err.is_empty());
+        for (i, expr) in golden_set.into_iter().enumerate() {
+            assert!(parser.exprs[i].eq(&expr));
+        }
+    }
+
+    #[test]
+    fn test_multi_digit_parameter_expansion() {
+        let line = "echo ${10}${#}";
+        let state = expr::State::new();
+        let mut parser = Parser::new(state);
+        let golden_set = Vec::from([AndOrNode::Pipeline(Box::new(PipeLineExpr {
+            pipeline: Vec::from([CompoundList::Commandexpr(CommandExpr {
+                command: Argument::Name("echo".to_string()),
+                arguments: Vec::from([Argument::Merge(MergeExpr {
+                    left: Box::new(Argument::Expansion(ExpansionExpr::ParameterExpansion(
+                        "10".to_string(),
+                    ))),
+                    right: Box::new(Argument::Expansion(ExpansionExpr::ParameterExpansion(
+                        "#".to_string(),
+                    ))),
+                })])
+                .into(),
+                assignment: None,
+                redirects: Vec::new(),
+            })]),
+            background: false,
+            state: expr::State::new(),
+            line: 1,
+        }))]);
+        parser.parse(line);
+        println!("{:#?}", parser.exprs);
+        assert!(parser.err.is_empty());
+        for (i, expr) in golden_set.into_iter().enumerate() {
+            assert!(parser.exprs[i].eq(&expr));
+        }
+    }
+
+    #[test]
+    fn test_empty_assignment() {
+        let line = "EMPTY=";
+        let state = expr::State::new();
+        let mut parser = Parser::new(state);
+        parser.parse(line);
+        assert!(parser.err.is_empty());
+        let AndOrNode::Pipeline(pl) = &parser.exprs[0] else {
+            panic!("expected a pipeline");
+        };
+        let CompoundList::Commandexpr(cmd) = &pl.pipeline[0] else {
+            panic!("expected a command");
+        };
+        assert_eq!(
+            cmd.assignment,
+            Some(AssignmentExpr {
+                key: "EMPTY".to_string(),
+                val: Argument::Name("".to_string()),
+            })
+        );
+    }
+
+    fn command_words(exprs: &[AndOrNode]) -> Vec<String> {
+        let AndOrNode::Pipeline(pl) = &exprs[0] else {
+            panic!("expected a pipeline");
+        };
+        let CompoundList::Commandexpr(cmd) = &pl.pipeline[0] else {
+            panic!("expected a command");
+        };
+        let mut words = vec![cmd.command.clone()];
+        words.extend(cmd.arguments.iter().cloned());
+        words
+            .into_iter()
+            .map(|word| match word {
+                Argument::Name(name) => name,
+                other => panic!("expected a plain word, found {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_alias_substitution() {
+        let state = expr::State::new();
+        state
+            .borrow_mut()
+            .aliases
+            .insert("ll".to_string(), "ls -l".to_string());
+        let mut parser = Parser::new(state);
+        parser.parse("ll /tmp; echo ll");
+        assert!(parser.err.is_empty());
+        assert_eq!(command_words(&parser.exprs), vec!["ls", "-l", "/tmp"]);
+        assert_eq!(command_words(&parser.exprs[1..]), vec!["echo", "ll"]);
+    }
+
+    #[test]
+    fn test_recursive_alias() {
+        let state = expr::State::new();
+        state
+            .borrow_mut()
+            .aliases
+            .insert("ls".to_string(), "ls -F".to_string());
+        state
+            .borrow_mut()
+            .aliases
+            .insert("l".to_string(), "ls -a".to_string());
+        let mut parser = Parser::new(state);
+        parser.parse("l /tmp");
+        assert!(parser.err.is_empty());
+        assert_eq!(command_words(&parser.exprs), vec!["ls", "-F", "-a", "/tmp"]);
+    }
+
+    #[test]
+    fn test_alias_trailing_blank() {
+        let state = expr::State::new();
+        state
+            .borrow_mut()
+            .aliases
+            .insert("sudo".to_string(), "sudo ".to_string());
+        state
+            .borrow_mut()
+            .aliases
+            .insert("ll".to_string(), "ls -l".to_string());
+        state
+            .borrow_mut()
+            .aliases
+            .insert("nope".to_string(), "echo".to_string());
+        let mut parser = Parser::new(state);
+        parser.parse("sudo ll\nnope ll");
+        assert!(parser.err.is_empty());
+        assert_eq!(command_words(&parser.exprs), vec!["sudo", "ls", "-l"]);
+        assert_eq!(command_words(&parser.exprs[1..]), vec!["echo", "ll"]);
+    }
+
+    #[test]
+    fn test_time_keyword() {
+        let state = expr::State::new();
+        let mut parser = Parser::new(state);
+        parser.parse("time -p sleep 1 | cat && time ls");
+        assert!(parser.err.is_empty());
+        let AndOrNode::Andif(and) = &parser.exprs[0] else {
+            panic!("expected an and-if, found {:?}", parser.exprs[0]);
+        };
+        let AndOrNode::Time(left) = &and.left else {
+            panic!("expected a timed pipeline, found {:?}", and.left);
+        };
+        assert!(left.posix);
+        assert_eq!(left.pipeline.pipeline.len(), 2);
+        let AndOrNode::Time(right) = &and.right else {
+            panic!("expected a timed pipeline, found {:?}", and.right);
+        };
+        assert!(!right.posix);
+        assert_eq!(
+            command_words(&[AndOrNode::Pipeline(Box::new(right.pipeline.clone()))]),
+            vec!["ls"]
+        );
+
+        for text in ["time", "time -p; ls", "time\nls", "time && ls"] {
+            let mut parser = Parser::new(expr::State::new());
+            parser.parse(text);
+            assert!(parser.err.is_empty(), "{text:?}: {}", parser.err);
+            let AndOrNode::Time(timed) = (match &parser.exprs[0] {
+                AndOrNode::Andif(and) => &and.left,
+                first => first,
+            }) else {
+                panic!("expected a timed pipeline, found {:?}", parser.exprs[0]);
+            };
+            assert!(timed.pipeline.pipeline.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_compound_redirects() {
+        let state = expr::State::new();
+        let mut parser = Parser::new(state);
+        parser.parse("for f in a b; do ls $f; done > out 2>&1 | cat");
+        assert!(parser.err.is_empty());
+        let AndOrNode::Pipeline(pipeline) = &parser.exprs[0] else {
+            panic!("expected a pipeline, found {:?}", parser.exprs[0]);
+        };
+        assert_eq!(pipeline.pipeline.len(), 2);
+        let CompoundList::Redirected(compound, redirects) = &pipeline.pipeline[0] else {
+            panic!("expected redirects, found {:?}", pipeline.pipeline[0]);
+        };
+        assert!(matches!(**compound, CompoundList::Forexpr(_)));
+        assert_eq!(
+            redirects
+                .iter()
+                .map(|r| r.file_descriptor)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_pattern_removal() {
+        let state = expr::State::new();
+        let mut parser = Parser::new(state);
+        parser.parse("echo ${f%%.*} ${f#\"*\"$x}");
+        assert!(parser.err.is_empty());
+        let AndOrNode::Pipeline(pipeline) = &parser.exprs[0] else {
+            panic!("expected a pipeline, found {:?}", parser.exprs[0]);
+        };
+        let CompoundList::Commandexpr(command) = &pipeline.pipeline[0] else {
+            panic!("expected a command, found {:?}", pipeline.pipeline[0]);
+        };
+        assert_eq!(
+            command.arguments[0],
+            Argument::Expansion(ExpansionExpr::RemoveLongestSuffix(
+                "f".to_string(),
+                PatternExpr {
+                    parts: vec![
+                        (Argument::Name(".".to_string()), false),
+                        (Argument::Name("*".to_string()), false)
+                    ],
+                },
+            ))
+        );
+        assert_eq!(
+            command.arguments[1],
+            Argument::Expansion(ExpansionExpr::RemoveShortestPrefix(
+                "f".to_string(),
+                PatternExpr {
+                    parts: vec![
+                        (Argument::QuoteString("*".to_string()), true),
+                        (
+                            Argument::Variable(VariableLookup {
+                                name: "x".to_string()
+                            }),
+                            false
+                        ),
+                    ],
+                },
+            ))
+        );
+    }
+
+    #[test]
+    fn test_word_expansions() {
+        let state = expr::State::new();
+        let mut parser = Parser::new(state);
+        parser.parse("echo ${a-x y} ${b:+$c} ${d=} ${e?no}");
+        assert!(parser.err.is_empty());
+        let AndOrNode::Pipeline(pipeline) = &parser.exprs[0] else {
+            panic!("expected a pipeline, found {:?}", parser.exprs[0]);
+        };
+        let CompoundList::Commandexpr(command) = &pipeline.pipeline[0] else {
+            panic!("expected a command, found {:?}", pipeline.pipeline[0]);
+        };
+        let name = |n: &str| Box::new(Argument::Name(n.to_string()));
+        assert_eq!(
+            *command.arguments,
+            vec![
+                Argument::Expansion(ExpansionExpr::ParameterSubstitute(
+                    "a".to_string(),
+                    Box::new(Argument::Merge(MergeExpr {
+                        left: Box::new(Argument::Merge(MergeExpr {
+                            left: name("x"),
+                            right: name(" "),
+                        })),
+                        right: name("y"),
+                    })),
+                    false,
+                )),
+                Argument::Expansion(ExpansionExpr::ParameterAlternate(
+                    "b".to_string(),
+                    Box::new(Argument::Variable(VariableLookup {
+                        name: "c".to_string(),
+                    })),
+                    true,
+                )),
+                Argument::Expansion(ExpansionExpr::ParameterAssign(
+                    "d".to_string(),
+                    name(""),
+                    false,
+                )),
+                Argument::Expansion(ExpansionExpr::ParameterError(
+                    "e".to_string(),
+                    name("no"),
+                    false,
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extension_expansions() {
+        let state = expr::State::new();
+        let mut parser = Parser::new(state);
+        parser.parse("echo ${a:1:2} ${b//x*/y} ${c/#p} ${d^^[ab]}");
+        assert!(parser.err.is_empty());
+        let AndOrNode::Pipeline(pipeline) = &parser.exprs[0] else {
+            panic!("expected a pipeline, found {:?}", parser.exprs[0]);
+        };
+        let CompoundList::Commandexpr(command) = &pipeline.pipeline[0] else {
+            panic!("expected a command, found {:?}", pipeline.pipeline[0]);
+        };
+        let name = |n: &str| Argument::Name(n.to_string());
+        assert_eq!(
+            *command.arguments,
+            vec![
+                Argument::Expansion(ExpansionExpr::Substring(
+                    "a".to_string(),
+                    Box::new(name("1:2")),
+                )),
+                Argument::Expansion(ExpansionExpr::Replace(
+                    "b".to_string(),
+                    PatternExpr {
+                        parts: vec![(name("x"), false), (name("*"), false)],
+                    },
+                    Box::new(name("y")),
+                    ReplaceMode::All,
+                )),
+                Argument::Expansion(ExpansionExpr::Replace(
+                    "c".to_string(),
+                    PatternExpr {
+                        parts: vec![(name("p"), false)],
+                    },
+                    Box::new(name("")),
+                    ReplaceMode::Prefix,
+                )),
+                Argument::Expansion(ExpansionExpr::ChangeCase(
+                    "d".to_string(),
+                    PatternExpr {
+                        parts: vec![(name("[ab]"), false)],
+                    },
+                    CaseChange::UpperAll,
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_incomplete_input() {
+        let state = expr::State::new();
+        for text in ["if true; then", "echo a |", "echo 'open", "cat <<EOF"] {
+            let mut parser = Parser::new(state.clone());
+            parser.parse(text);
+            assert!(parser.incomplete, "{text:?} should want more input");
+        }
+        for text in [
+            "if true; then\necho yes\nfi",
+            "echo a |\nwc -l",
+            "echo \"$\"",
+        ] {
+            let mut parser = Parser::new(state.clone());
+            parser.parse(text);
+            assert!(!parser.incomplete, "{text:?} is complete");
+            assert!(parser.err.is_empty(), "{text:?}: {}", parser.err);
+        }
+        // closed but wrong, these are syntax errors and not a reason to wait
+        for text in [
+            "if true; fi",
+            "f() echo",
+            "echo $",
+            "echo )",
+            "echo x done!",
+        ] {
+            let mut parser = Parser::new(state.clone());
+            parser.parse(text);
+            assert!(!parser.incomplete && !parser.err.is_empty(), "{text:?}");
+        }
+    }
 }
@@ -22,6 +22,8 @@ pub enum ShTokenType {
     RedirectOut,        // >
     RedirectIn,         // <
     AppendOut,          // >>
+    DuplicateOut,       // >&
+    DuplicateIn,        // <&
     AndIf,              // &&
     OrIf,               // ||
     Equal,              // =
@@ -58,6 +60,7 @@ pub enum ShTokenType {
 pub struct Token {
     pub lexeme: String,
     pub token_type: ShTokenType,
+    pub line: usize, // the line the token starts on, counting from 1
 }
 
 impl Token {
@@ -78,38 +81,73 @@ pub fn is_delimiter(c: char) -> bool {
     delimiter_set.contains(&c)
 }
 
+const KEYWORDS: [(&str, ShTokenType); 17] = [
+    ("case", ShTokenType::Case),
+    ("do", ShTokenType::Do),
+    ("done", ShTokenType::Done),
+    ("elif", ShTokenType::Elif),
+    ("else", ShTokenType::Else),
+    ("esac", ShTokenType::Esac),
+    ("fi", ShTokenType::Fi),
+    ("for", ShTokenType::For),
+    ("if", ShTokenType::If),
+    ("in", ShTokenType::In),
+    ("then", ShTokenType::Then),
+    ("until", ShTokenType::Until),
+    ("while", ShTokenType::While),
+    ("function", ShTokenType::Function),
+    ("namespace", ShTokenType::NameSpace),
+    ("select", ShTokenType::Select),
+    ("time", ShTokenType::Time),
+];
+
+pub fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|(keyword, _)| *keyword == word)
+}
+
+// The inside of a double quoted string up to its closing quote. Quotes start
+// over inside a ${...} in it, so "${x:-"a b"}" is one string, not two.
+fn scan_double_quoted(it: &mut Peekable<Chars<'_>>) -> Result<String, String> {
+    let mut ret = String::new();
+    let mut depth = 0;
+    while let Some(c) = it.next() {
+        ret.push(c);
+        match c {
+            '"' if depth == 0 => {
+                ret.pop();
+                return Ok(ret);
+            }
+            '"' => {
+                ret += &scan_double_quoted(it)?;
+                ret.push('"');
+            }
+            '$' if it.peek() == Some(&'{') => {
+                ret.extend(it.next());
+                depth += 1;
+            }
+            '}' if depth > 0 => depth -= 1,
+            _ => {}
+        }
+    }
+    Err("Couldn't find second '\"'.".to_string())
+}
+
 pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
     let mut tokens: Vec<Token> = Vec::new();
     let mut current;
-    let token_map: HashMap<&str, ShTokenType> = HashMap::from([
-        ("case", ShTokenType::Case),
-        ("do", ShTokenType::Do),
-        ("done", ShTokenType::Done),
-        ("elif", ShTokenType::Elif),
-        ("else", ShTokenType::Else),
-        ("esac", ShTokenType::Esac),
-        ("fi", ShTokenType::Fi),
-        ("for", ShTokenType::For),
-        ("if", ShTokenType::If),
-        ("in", ShTokenType::In),
-        ("then", ShTokenType::Then),
-        ("until", ShTokenType::Until),
-        ("while", ShTokenType::While),
-        ("function", ShTokenType::Function),
-        ("namespace", ShTokenType::NameSpace),
-        ("select", ShTokenType::Select),
-        ("time", ShTokenType::Time),
-    ]);
+    let token_map: HashMap<&str, ShTokenType> = HashMap::from(KEYWORDS);
 
-    let match_token = |current: String| -> Token {
+    let match_token = |current: String, line: usize| -> Token {
         match token_map.get(&current.as_str()) {
             Some(&toke_type) => Token {
                 lexeme: current.clone(),
                 token_type: toke_type,
+                line,
             },
             None => Token {
                 lexeme: current.clone(),
                 token_type: ShTokenType::Name,
+                line,
             },
         }
     };
@@ -127,16 +165,20 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
     };
     let mut in_sub = false;
     let mut it = st.chars().peekable();
+    let mut line = 1;
     while let Some(c) = it.next() {
         let token = match c {
             '#' => {
+                // inside double quotes there are no comments
                 if in_sub
+                    || in_quoted_string
                     || tokens.last().is_some()
                         && tokens.last().unwrap().token_type == ShTokenType::DollarSign
                 {
                     Token {
                         lexeme: String::from('#'),
                         token_type: ShTokenType::Pound,
+                        line,
                     }
                 } else {
                     // this is ugly
@@ -148,69 +190,86 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
                     Token {
                         lexeme: String::from('\n'),
                         token_type: ShTokenType::NewLine,
+                        line,
                     }
                 }
             }
             '\n' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::NewLine,
+                line,
             },
             ' ' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::WhiteSpace,
+                line,
             },
             '\\' => {
                 if in_quoted_string {
                     Token {
                         lexeme: String::from("\\"),
                         token_type: ShTokenType::Name,
+                        line,
                     }
                 } else if let Some(cc) = it.next() {
                     match cc {
-                        '\n' | ' ' => {
+                        '\n' => {
+                            line += 1;
+                            continue;
+                        }
+                        ' ' => {
                             continue;
                         }
                         _ => Token {
                             lexeme: String::from(cc),
                             token_type: ShTokenType::Name,
+                            line,
                         },
                     }
                 } else {
                     Token {
                         lexeme: String::from(""),
                         token_type: ShTokenType::EndOfFile,
+                        line,
                     }
                 }
             }
             '$' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::DollarSign,
+                line,
             },
             '`' => Token {
                 lexeme: scan_until('`', &mut it)?,
                 token_type: ShTokenType::BackTickStr,
+                line,
             },
             '"' => Token {
-                lexeme: scan_until('"', &mut it)?,
+                lexeme: scan_double_quoted(&mut it)?,
                 token_type: ShTokenType::DoubleQuoteStr,
+                line,
             },
             '\'' => Token {
                 lexeme: scan_until('\'', &mut it)?,
                 token_type: ShTokenType::SingleQuoteStr,
+                line,
             },
             '(' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::LeftParen,
+                line,
             },
             ')' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::RightParen,
+                line,
             },
             '{' => {
                 in_sub = true;
                 Token {
                     lexeme: String::from(c),
                     token_type: ShTokenType::LeftBrace,
+                    line,
                 }
             }
             '}' => {
@@ -218,27 +277,33 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
                 Token {
                     lexeme: String::from(c),
                     token_type: ShTokenType::RightBrace,
+                    line,
                 }
             }
             '@' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::AtSign,
+                line,
             },
             '*' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::Star,
+                line,
             },
             '~' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::Tilde,
+                line,
             },
             '=' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::Equal,
+                line,
             },
             ';' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::SemiColon,
+                line,
             },
             ':' => {
                 let tok: Token;
@@ -246,30 +311,35 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
                     tok = Token {
                         lexeme: String::from(":-"),
                         token_type: ShTokenType::UseDefault,
+                        line,
                     };
                     it.next();
                 } else if it.peek().is_some() && *it.peek().expect("No char?") == '=' {
                     tok = Token {
                         lexeme: String::from(":="),
                         token_type: ShTokenType::AssignDefault,
+                        line,
                     };
                     it.next();
                 } else if it.peek().is_some() && *it.peek().expect("No char?") == '?' {
                     tok = Token {
                         lexeme: String::from(":?"),
                         token_type: ShTokenType::ErrorOn,
+                        line,
                     };
                     it.next();
                 } else if it.peek().is_some() && *it.peek().expect("No char?") == '+' {
                     tok = Token {
                         lexeme: String::from(":+"),
                         token_type: ShTokenType::UseNullOrDefault,
+                        line,
                     };
                     it.next();
                 } else {
                     tok = Token {
                         lexeme: String::from(c),
                         token_type: ShTokenType::Name,
+                        line,
                     };
                 }
                 tok
@@ -280,12 +350,14 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
                     tok = Token {
                         lexeme: String::from("[["),
                         token_type: ShTokenType::DoubleLeftBracket,
+                        line,
                     };
                     it.next();
                 } else {
                     tok = Token {
                         lexeme: String::from(c),
                         token_type: ShTokenType::Name,
+                        line,
                     };
                 }
                 tok
@@ -296,12 +368,14 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
                     tok = Token {
                         lexeme: String::from("]]"),
                         token_type: ShTokenType::DoubleRightBracket,
+                        line,
                     };
                     it.next();
                 } else {
                     tok = Token {
                         lexeme: String::from(c),
                         token_type: ShTokenType::Name,
+                        line,
                     }
                 }
                 tok
@@ -312,12 +386,14 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
                     tok = Token {
                         lexeme: String::from("&&"),
                         token_type: ShTokenType::AndIf,
+                        line,
                     };
                     it.next();
                 } else {
                     tok = Token {
                         lexeme: String::from(c),
                         token_type: ShTokenType::Control,
+                        line,
                     }
                 }
                 tok
@@ -328,12 +404,14 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
                     tok = Token {
                         lexeme: String::from("||"),
                         token_type: ShTokenType::OrIf,
+                        line,
                     };
                     it.next();
                 } else {
                     tok = Token {
                         lexeme: String::from(c),
                         token_type: ShTokenType::Pipe,
+                        line,
                     }
                 }
                 tok
@@ -344,23 +422,47 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
                     tok = Token {
                         lexeme: String::from(">>"),
                         token_type: ShTokenType::AppendOut,
+                        line,
+                    };
+                    it.next();
+                } else if it.peek().is_some() && *it.peek().unwrap() == '&' {
+                    tok = Token {
+                        lexeme: String::from(">&"),
+                        token_type: ShTokenType::DuplicateOut,
+                        line,
                     };
                     it.next();
                 } else {
                     tok = Token {
                         lexeme: String::from(c),
                         token_type: ShTokenType::RedirectOut,
+                        line,
+                    }
+                }
+                tok
+            }
+            '<' => {
+                let tok: Token;
+                if it.peek().is_some() && *it.peek().unwrap() == '&' {
+                    tok = Token {
+                        lexeme: String::from("<&"),
+                        token_type: ShTokenType::DuplicateIn,
+                        line,
+                    };
+                    it.next();
+                } else {
+                    tok = Token {
+                        lexeme: String::from(c),
+                        token_type: ShTokenType::RedirectIn,
+                        line,
                     }
                 }
                 tok
             }
-            '<' => Token {
-                lexeme: String::from(c),
-                token_type: ShTokenType::RedirectIn,
-            },
             '!' => Token {
                 lexeme: String::from(c),
                 token_type: ShTokenType::Bang,
+                line,
             },
             _ => {
                 current = String::from(c);
@@ -372,10 +474,13 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
                         break;
                     }
                 }
-                match_token(current)
+                match_token(current, line)
             }
         };
 
+        // newlines inside quotes and the one ending a comment count too
+        line += token.lexeme.matches('\n').count();
+
         // this works fines for single quoted strings, but i don't like the idea
         // of the 'tokenizer' having to call executable code and stuff....
         if tokens.last().is_some()
@@ -386,6 +491,7 @@ pub fn tokens(st: &str, in_quoted_string: bool) -> Result<Vec<Token>, String> {
             tokens.push(Token {
                 lexeme: last.lexeme + token.lexeme.as_str(),
                 token_type: ShTokenType::Name,
+                line: last.line,
             });
         } else {
             tokens.push(token);
@@ -406,106 +512,132 @@ mod tests {
             Token {
                 lexeme: String::from("|"),
                 token_type: ShTokenType::Pipe,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("||"),
                 token_type: ShTokenType::OrIf,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from(">"),
                 token_type: ShTokenType::RedirectOut,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from(">>"),
                 token_type: ShTokenType::AppendOut,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("<"),
                 token_type: ShTokenType::RedirectIn,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("["),
                 token_type: ShTokenType::Name,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("[["),
                 token_type: ShTokenType::DoubleLeftBracket,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("]"),
                 token_type: ShTokenType::Name,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("]]"),
                 token_type: ShTokenType::DoubleRightBracket,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("&&"),
                 token_type: ShTokenType::AndIf,
+                line: 1,
             },
             Token {
                 lexeme: String::from("&"),
                 token_type: ShTokenType::Control,
+                line: 1,
             },
             Token {
                 lexeme: String::from("~"),
                 token_type: ShTokenType::Tilde,
+                line: 1,
             },
             Token {
                 lexeme: String::from("$"),
                 token_type: ShTokenType::DollarSign,
+                line: 1,
             },
             Token {
                 lexeme: String::from("{"),
                 token_type: ShTokenType::LeftBrace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("}"),
                 token_type: ShTokenType::RightBrace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("@"),
                 token_type: ShTokenType::AtSign,
+                line: 1,
             },
             Token {
                 lexeme: String::from("*"),
                 token_type: ShTokenType::Star,
+                line: 1,
             },
         ];
         assert!(good_graces.iter().eq(toks.iter()));
@@ -519,38 +651,47 @@ mod tests {
             Token {
                 lexeme: String::from("if"),
                 token_type: ShTokenType::If,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("elif"),
                 token_type: ShTokenType::Elif,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("else"),
                 token_type: ShTokenType::Else,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("fi"),
                 token_type: ShTokenType::Fi,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("while"),
                 token_type: ShTokenType::While,
+                line: 1,
             },
         ];
         assert!(good_graces.iter().eq(toks.iter()));
@@ -564,28 +705,67 @@ mod tests {
             Token {
                 lexeme: String::from("if"),
                 token_type: ShTokenType::If,
+                line: 1,
             },
             Token {
                 lexeme: String::from("|"),
                 token_type: ShTokenType::Pipe,
+                line: 1,
             },
             Token {
                 lexeme: String::from("while"),
                 token_type: ShTokenType::While,
+                line: 1,
             },
             Token {
                 lexeme: String::from("{"),
                 token_type: ShTokenType::LeftBrace,
+                line: 1,
             },
             Token {
                 lexeme: String::from(" "),
                 token_type: ShTokenType::WhiteSpace,
+                line: 1,
             },
             Token {
                 lexeme: String::from("elif"),
                 token_type: ShTokenType::Elif,
+                line: 1,
             },
         ];
         assert!(good_graces.iter().eq(toks.iter()));
     }
+
+    #[test]
+    fn token_lines() {
+        let toks = tokens("a 'x\ny' \\\nb # c\nd", false).unwrap();
+        let lines: Vec<(&str, usize)> = toks
+            .iter()
+            .filter(|tok| tok.token_type != ShTokenType::WhiteSpace)
+            .map(|tok| (tok.lexeme.as_str(), tok.line))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![("a", 1), ("x\ny", 1), ("b", 3), ("\n", 3), ("d", 4)]
+        );
+    }
+
+    #[test]
+    fn nested_double_quotes() {
+        let quoted = |st: &str| -> Vec<String> {
+            tokens(st, false)
+                .unwrap()
+                .into_iter()
+                .filter(|tok| tok.token_type == ShTokenType::DoubleQuoteStr)
+                .map(|tok| tok.lexeme)
+                .collect()
+        };
+        assert_eq!(quoted("\"${g#\"$p\"}\""), ["${g#\"$p\"}"]);
+        assert_eq!(
+            quoted("\"a ${g%%\"${h#\"*\"}\"} b\" \"}\""),
+            ["a ${g%%\"${h#\"*\"}\"} b", "}"]
+        );
+        assert_eq!(quoted("\"${g##\"*\"}\"\"\""), ["${g##\"*\"}", ""]);
+        assert!(tokens("\"${g#\"$p}\"", false).is_err());
+    }
 }
@@ -0,0 +1,256 @@
+use crate::parser::tokenizer::{ShTokenType, Token, tokens};
+use std::collections::VecDeque;
+
+// What an open construct is waiting for.
+#[derive(Debug, PartialEq)]
+enum Closer {
+    Word(ShTokenType), // fi, done or esac
+    Group,             // } of { ...; }, only where a command could start
+    Parameter,         // } of ${...}
+    Paren,             // ) of a subshell or function header
+    Substitution,      // ) of $(...)
+}
+
+// Whether text stops partway through something more lines could finish:
+// a quote or here-document that isn't closed, a line ending in \, |, &&
+// or ||, or an if, while, until, for, case, function, { } or ( ) still
+// open. Anything else wrong with it is a plain syntax error.
+pub fn unfinished(text: &str) -> bool {
+    let (text, pending) = without_heredocs(text);
+    if pending {
+        return true;
+    }
+    match tokens(&text, false) {
+        Ok(tokens) => open_constructs(&tokens),
+        // the only thing the tokenizer fails on is a quote left open
+        Err(_) => true,
+    }
+}
+
+// Blanks out here-document bodies so what they hold isn't taken for shell
+// syntax. The flag is set when one is still waiting for its end word.
+fn without_heredocs(text: &str) -> (String, bool) {
+    let mut kept = Vec::new();
+    let mut pending: VecDeque<(String, bool)> = VecDeque::new();
+    for line in text.split('\n') {
+        if let Some((word, strip_tabs)) = pending.front() {
+            let body = if *strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                line
+            };
+            if body == word {
+                pending.pop_front();
+            }
+            kept.push("");
+            continue;
+        }
+        pending.extend(heredoc_words(line));
+        kept.push(line);
+    }
+    (kept.join("\n"), !pending.is_empty())
+}
+
+// The end words of the << operators on a line, in order, each with whether
+// it was <<- and so may be indented with tabs. Quotes around a word go.
+fn heredoc_words(line: &str) -> Vec<(String, bool)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut words = Vec::new();
+    let mut quote = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '\\' => i += 1,
+            '\'' | '"' | '`' => quote = Some(c),
+            '#' if i == 1 || chars[i - 2].is_whitespace() => break,
+            // <<< is a here-string, there is no body to wait for
+            '<' if chars.get(i) == Some(&'<') && chars.get(i + 1) == Some(&'<') => i += 2,
+            '<' if chars.get(i) == Some(&'<') => {
+                i += 1;
+                let strip_tabs = chars.get(i) == Some(&'-');
+                if strip_tabs {
+                    i += 1;
+                }
+                while chars.get(i).is_some_and(|c| *c == ' ' || *c == '\t') {
+                    i += 1;
+                }
+                let mut word = String::new();
+                let mut word_quote = None;
+                while let Some(&c) = chars.get(i) {
+                    match word_quote {
+                        Some(q) if c == q => word_quote = None,
+                        Some(_) => word.push(c),
+                        None if c == '\'' || c == '"' => word_quote = Some(c),
+                        None if c == '\\' => {}
+                        None if c.is_whitespace() || ";&|<>()".contains(c) => break,
+                        None => word.push(c),
+                    }
+                    i += 1;
+                }
+                if !word.is_empty() {
+                    words.push((word, strip_tabs));
+                }
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+// Keeps a stack of what still has to close while going over the tokens.
+// Reserved words only count where a command could start.
+fn open_constructs(tokens: &[Token]) -> bool {
+    use ShTokenType::*;
+    let mut open: Vec<Closer> = Vec::new();
+    let mut command_start = true;
+    // the last few tokens that weren't blanks or newlines
+    let mut recent: Vec<ShTokenType> = Vec::new();
+    for token in tokens {
+        let kind = token.token_type;
+        let in_case = open.last() == Some(&Closer::Word(Esac));
+        match kind {
+            WhiteSpace => continue,
+            // only a backslash at the very end leaves one of these behind
+            EndOfFile => return true,
+            NewLine | SemiColon | Control | AndIf | OrIf | Pipe => command_start = true,
+            If if command_start => open.push(Closer::Word(Fi)),
+            While | Until if command_start => open.push(Closer::Word(Done)),
+            For if command_start => {
+                open.push(Closer::Word(Done));
+                command_start = false;
+            }
+            Case if command_start => {
+                open.push(Closer::Word(Esac));
+                command_start = false;
+            }
+            Then | Else | Elif | Do | Bang | Time if command_start => {}
+            Fi | Done if command_start && open.last() == Some(&Closer::Word(kind)) => {
+                open.pop();
+                command_start = false;
+            }
+            Esac if in_case => {
+                open.pop();
+                command_start = false;
+            }
+            LeftBrace if recent.last() == Some(&DollarSign) => open.push(Closer::Parameter),
+            LeftBrace if command_start => open.push(Closer::Group),
+            RightBrace if open.last() == Some(&Closer::Parameter) => {
+                open.pop();
+            }
+            RightBrace if command_start && open.last() == Some(&Closer::Group) => {
+                open.pop();
+                command_start = false;
+            }
+            LeftParen if recent.last() == Some(&DollarSign) => {
+                open.push(Closer::Substitution);
+                command_start = true;
+            }
+            // the parens around case patterns don't nest anything
+            LeftParen if in_case => command_start = false,
+            RightParen if in_case => command_start = true,
+            LeftParen => {
+                open.push(Closer::Paren);
+                command_start = true;
+            }
+            RightParen if open.last() == Some(&Closer::Substitution) => {
+                open.pop();
+                command_start = false;
+            }
+            RightParen if open.last() == Some(&Closer::Paren) => {
+                open.pop();
+                command_start = true;
+            }
+            _ => command_start = false,
+        }
+        if kind != NewLine {
+            recent.push(kind);
+        }
+    }
+    let trailing_operator = matches!(recent.last(), Some(Pipe | AndIf | OrIf));
+    // name() with its body still to come
+    let function_header = recent.ends_with(&[Name, LeftParen, RightParen]);
+    !open.is_empty() || trailing_operator || function_header
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use crate::parser::unfinished::{heredoc_words, unfinished, without_heredocs};
+
+    #[test]
+    fn test_heredoc_words() {
+        let word = |w: &str, strip: bool| (w.to_string(), strip);
+        assert_eq!(heredoc_words("cat <<EOF"), [word("EOF", false)]);
+        assert_eq!(
+            heredoc_words("cat <<- 'END' | tr a b <<\"x\"y"),
+            [word("END", true), word("xy", false)]
+        );
+        assert!(heredoc_words("cat <<<word '<<EOF' # <<EOF").is_empty());
+    }
+
+    #[test]
+    fn test_without_heredocs() {
+        assert_eq!(
+            without_heredocs("cat <<EOF\nif 'x\nEOF\necho done"),
+            ("cat <<EOF\n\n\necho done".to_string(), false)
+        );
+        assert!(without_heredocs("cat <<-EOF\n\tbody").1);
+        assert!(!without_heredocs("cat <<-EOF\n\tbody\n\tEOF").1);
+    }
+
+    #[test]
+    fn test_unfinished() {
+        for text in [
+            "if true; then",
+            "if true; then\necho yes",
+            "while true",
+            "until false; do",
+            "for i in 1 2",
+            "case x in",
+            "case x in\na) echo a;;",
+            "f() {",
+            "f()",
+            "{ echo",
+            "( echo",
+            "echo $(ls",
+            "echo ${x",
+            "echo a |",
+            "true &&",
+            "false ||\n",
+            "echo \"open",
+            "echo 'open",
+            "echo `ls",
+            "echo a \\",
+            "cat <<EOF\nbody",
+        ] {
+            assert!(unfinished(text), "{text:?} should want more input");
+        }
+        for text in [
+            "",
+            "if true; then\necho yes\nfi",
+            "for i in 1 2; do echo $i; done",
+            "case x in\n(a) echo a;;\nb) echo $(ls);;\nesac",
+            "f() { echo; }",
+            "echo $(ls) ${x} (a)",
+            "echo if for { done",
+            "echo a |\nwc -l",
+            "echo \"two\nlines\"",
+            "cat <<EOF\nif (\nEOF",
+            // wrong rather than unfinished
+            "fi",
+            "echo )",
+            "echo }",
+            "done",
+            "echo $",
+        ] {
+            assert!(!unfinished(text), "{text:?} is finished");
+        }
+    }
+}
@@ -0,0 +1,12 @@
+#!/bin/sh
+
+# an alias is there from the next command on, not for the rest of its own line
+alias greet='echo hello'
+greet world
+alias shout='echo LOUD'; echo same line
+shout it
+
+if true; then
+    greet from an if
+fi
+unalias greet
@@ -0,0 +1,39 @@
+BASE=/tmp/slush_cd_test
+rm -rf $BASE
+mkdir -p $BASE/real/inner $BASE/other
+ln -s $BASE/real $BASE/link
+
+cd $BASE/link
+pwd
+pwd -P
+echo $PWD
+cd inner
+cd ..
+pwd
+cd -P ..
+pwd
+cd $BASE/link/inner
+cd -P ..
+pwd
+
+cd $BASE/other
+cd $BASE/real
+echo $OLDPWD
+cd -
+pwd
+echo $OLDPWD
+
+CDPATH=$BASE
+cd real
+CDPATH=
+cd /tmp/slush_cd_test/link
+cd ./inner
+pwd
+
+cd /nonexistent/dir 2> /dev/null || echo "cd failed"
+pwd
+
+HOME=$BASE/other
+cd
+pwd
+rm -rf $BASE
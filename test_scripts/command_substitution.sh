@@ -0,0 +1,30 @@
+x=$(printf 'a\n\n\n')
+echo "[$x]"
+greet() { echo hello; }
+y=$(greet)
+echo "[$y]"
+z=$(printf 'c\nb\na\n' | sort | head -n 2)
+echo "[$z]"
+w=$(pwd; type cd)
+echo "$w" | wc -l
+v=$(printf '\377ok')
+echo ${#v} > /dev/null
+a=1
+b=$(a=2; echo $a)
+echo $a $b
+c=$(for i in 1 2 3; do echo $i; done | tail -n 1)
+echo $c
+n=`echo back; echo tick`
+echo "$n"
+x=$(false)
+echo $?
+x=$(exit 3)
+echo $?
+x=$(exit 3)$(true)
+echo $?
+false
+x=plain
+echo $?
+false
+x=$?
+echo $x $?
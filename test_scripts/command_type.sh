@@ -0,0 +1,24 @@
+greet() {
+    echo "function greet"
+}
+
+type cd
+type greet
+type if
+type nothing_named_this
+echo $?
+command -v cd
+command -v greet
+command -v nothing_named_this
+echo $?
+command -V true
+command -v sh >/dev/null && echo "found sh"
+command -v nothing_named_this >/dev/null || echo "no nothing_named_this"
+
+cd() {
+    echo "not changing directory"
+}
+cd /
+command cd /
+pwd
+command -p printf "%s\n" hello
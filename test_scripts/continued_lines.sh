@@ -0,0 +1,19 @@
+echo one two |
+wc -w
+false ||
+echo after or
+true &&
+echo after and
+for i in 1 2
+do
+    echo $i |
+    cat
+done
+echo "a quote
+over lines"
+echo a backslash \
+continued
+if true
+then
+    echo then
+fi
@@ -0,0 +1,18 @@
+#!/bin/sh
+
+TMP=$(mktemp -d)
+
+exec 3>${TMP}/log
+echo "to fd three" >&3
+echo "and again" >&3
+cat ${TMP}/log
+
+exec 4<${TMP}/log
+head -1 <&4
+exec 4<&-
+
+rm -rf ${TMP}
+# the way a container entrypoint hands over, one argument per word
+set -- printf '[%s]\n' replaced "by exec"
+exec "$@"
+echo "this should not run"
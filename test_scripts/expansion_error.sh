@@ -0,0 +1,10 @@
+set_v=ok
+echo "start ${set_v:?unused}"
+{ x=$(echo ${gone?missing}; echo not reached); } 2>/dev/null
+echo "after [$x]"
+f() { echo "in f ${1:?need an arg}"; }
+y=$(f 2>/dev/null) 
+echo "f gave [$y]"
+echo before
+{ echo ${gone:?bye}; } 2>/dev/null
+echo never
@@ -0,0 +1,30 @@
+#!/bin/sh
+
+parse() {
+    OPTIND=1
+    while getopts vo:x opt; do
+        if [ $opt = o ]; then
+            echo "option o with $OPTARG"
+        elif [ $opt = '?' ]; then
+            echo "bad option"
+        else
+            echo "option $opt"
+        fi
+    done
+    shift $(expr $OPTIND - 1)
+    echo "remaining: $@"
+}
+
+parse -v -o file one two
+parse -vx -ofile2 three
+parse -vo file3 -- -x
+parse plain -v
+
+silent() {
+    OPTIND=1
+    while getopts :a: opt; do
+        echo "$opt $OPTARG"
+    done
+}
+
+silent -q -a
@@ -0,0 +1,30 @@
+hash -r
+hash
+hash sh
+hash
+hash nothing_named_this 2> /dev/null
+echo $?
+hash -r
+hash
+nothing_named_this 2> /dev/null
+echo $?
+/etc/passwd 2> /dev/null
+echo $?
+/nothing/named/this 2> /dev/null
+echo $?
+OLDPATH=$PATH
+PATH=/nonexistent
+ls 2> /dev/null
+STATUS=$?
+PATH=$OLDPATH
+echo $STATUS
+ls / > /dev/null && echo "found ls again"
+script=/tmp/slush_hash_test_script
+echo 'echo "no shebang: $1"' > $script
+chmod +x $script
+$script arg
+echo $?
+echo '#!/nothing/named/this' > $script
+$script 2> /dev/null
+echo $?
+rm -f $script
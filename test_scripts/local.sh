@@ -0,0 +1,33 @@
+#!/bin/sh
+
+i=outer
+
+helper() {
+    local i
+    echo "helper starts with $i"
+    for i in 1 2 3; do
+        echo "helper $i"
+    done
+    inner
+}
+
+inner() {
+    echo "inner sees $i"
+    local i=inner
+    echo "inner set $i"
+}
+
+helper
+echo "after helper $i"
+
+countdown() {
+    local n=$1
+    if [ $n -gt 0 ]; then
+        echo $n
+        countdown $(expr $n - 1)
+        echo "back in $n"
+    fi
+}
+
+countdown 3
+echo "[$n]"
@@ -0,0 +1,21 @@
+f=/usr/local/lib/archive.tar.gz
+echo ${f#*/}
+echo ${f##*/}
+echo ${f%.*}
+echo ${f%%.*}
+echo "${f##*/}"
+ext=.gz
+echo ${f%$ext}
+echo ${f%'.gz'}
+star='*'
+s='a*b*c'
+echo "${s#*\*}" ${s#"$star"} ${s#a"*"}
+echo ${s%%[*]*}
+echo ${f#/usr/}
+echo ${f#nomatch}
+n=file123
+echo ${n%%[0-9]*} ${n#[[:alpha:]]}
+g=abcabc
+p=ab
+echo "${g#"$p"}" "${g##"$p"*}" "${g%"c"}" "[${g%%"b"*}]"
+echo "${f##*"/"}" "${s#"a*"}" "${s%"$star"*}"
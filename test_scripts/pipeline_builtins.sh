@@ -0,0 +1,12 @@
+greet() { echo hi; echo there; }
+greet | grep there
+command -v cd | cat
+greet | greet | wc -l
+type cd | tr a-z A-Z
+yes | true
+echo done1
+count() { while read l; do echo "got $l"; done; }
+printf 'a\nb\n' | count
+greet | count | sort -r
+pwd | cat > /dev/null
+echo ok
@@ -0,0 +1,14 @@
+for f in c a b; do echo $f; done | sort
+printf 'one\ntwo\n' > /tmp/slush_list
+while read l; do echo "line $l"; done < /tmp/slush_list
+for f in x y; do echo $f; done > /tmp/slush_out
+cat /tmp/slush_out
+{ echo b; echo a; } | sort
+if true; then echo yes; fi | tr a-z A-Z
+printf '1\n2\n3\n' | while read n; do echo "n=$n"; done | grep 2
+{ echo err 1>&2; } 2>&1 | cat
+{ echo first; echo second; } >> /tmp/slush_out
+cat /tmp/slush_out
+x=outer
+printf 'inner\n' | { read x; echo $x; }
+rm -f /tmp/slush_list /tmp/slush_out
@@ -0,0 +1,31 @@
+#!/bin/sh
+
+many() {
+    echo $# ${10} ${11} $10
+    shift
+    echo $# $1 ${10}
+    shift 3
+    echo $# $@
+}
+
+many a b c d e f g h i j k
+
+set -- one two three
+echo $# $1 $3
+shift 2
+echo $# $1
+set -- "a b" c
+count() {
+    echo $#
+}
+count "$@"
+count "x$@y"
+for word in "$@"; do
+    echo "<$word>"
+done
+set --
+echo $# cleared
+count "$@"
+count "$*"
+
+echo $0
@@ -0,0 +1,52 @@
+INPUT=/tmp/slush_read_test
+printf 'one two three four\n' > $INPUT
+read a b < $INPUT
+echo "a=$a b=$b"
+read a b c d e < $INPUT
+echo "d=$d e=$e"
+
+printf '  padded   words  \n' > $INPUT
+read line < $INPUT
+echo "[$line]"
+
+printf 'h\303\251llo w\303\266rld\n' > $INPUT
+read w1 w2 < $INPUT
+echo "$w2 $w1"
+
+printf 'a\\ b c\\\nd\n' > $INPUT
+read p q < $INPUT
+echo "p=$p q=$q"
+read -r p q < $INPUT
+echo "p=$p q=$q"
+
+printf 'x:y::z\n' > $INPUT
+OLDIFS=$IFS
+IFS=:
+read i j k l < $INPUT
+IFS=$OLDIFS
+echo "$i $j [$k] $l"
+
+printf 'no newline' > $INPUT
+read x < $INPUT
+echo "status $? x=$x"
+
+printf 'l1\nl2\nl3\n' > $INPUT
+exec 3< $INPUT
+while read -r line <&3; do
+    echo "got $line"
+done
+exec 3<&-
+read -p "prompt> " answer < $INPUT 2> /dev/null
+echo "answer=$answer"
+
+unset IFS
+printf '  a:b c  \nd:e f\n' > $INPUT
+exec 3< $INPUT
+IFS= read -r line <&3
+read first rest <&3
+exec 3<&-
+echo "[$line] [$first] [$rest]"
+IFS=: read i j < $INPUT
+read i j < $INPUT
+echo "[$i] [$j]"
+rm $INPUT
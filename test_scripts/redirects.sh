@@ -0,0 +1,18 @@
+#!/bin/sh
+
+TMP=$(mktemp -d)
+
+echo "to a file" > ${TMP}/out
+cat ${TMP}/out
+echo "appended" >> ${TMP}/out
+cat < ${TMP}/out
+ls ${TMP}/missing 2>/dev/null || echo "ls failed quietly"
+ls ${TMP}/missing 2>&1 >/dev/null | wc -l
+
+rm -rf ${TMP}
+failed=$(exec 2> /dev/null; cat < /missing/file; [ $? -ne 0 ] && echo failed)
+echo "cat: $failed"
+failed=$(exec 2> /dev/null; echo > /missing/dir/file; [ $? -ne 0 ] && echo failed)
+echo "echo: $failed"
+reached=$(exec 2> /dev/null; exec 3< /missing/file; echo reached)
+echo "exec: [$reached]"
@@ -16,3 +16,13 @@ positionals "hello" old $#
 
 counters 1 2 3
 counters 1    2 3 how
+
+echo "running as $0"
+if [ "$PPID" -gt 0 ]; then
+    echo "has a parent"
+fi
+parent=$PPID
+inner=$(echo $PPID)
+if [ "$parent" = "$inner" ]; then
+    echo "subshells keep PPID"
+fi
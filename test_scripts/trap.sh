@@ -0,0 +1,17 @@
+trap 'echo "exit trap, status $?"' EXIT
+trap
+x=$(trap 'echo inner trap' EXIT; echo sub)
+echo "$x"
+y=$(echo "[$(trap)]")
+echo "subshells start without traps $y"
+trap 'echo not this one' EXIT
+trap - EXIT
+trap
+trap 'echo "exit trap, status $?"' 0
+trap 'echo nope' NOSUCHSIGNAL 2> /dev/null
+echo "bad condition $?"
+z=$(trap 'echo "bye $?"' EXIT; exit 4)
+echo "$z, then $?"
+exec 2> /dev/null
+echo "${unset_variable?is not set}"
+echo not reached
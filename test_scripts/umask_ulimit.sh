@@ -0,0 +1,25 @@
+umask 022
+umask
+umask -S
+umask 027
+umask
+umask -S
+umask u=rwx,g=rx,o=
+umask
+umask g+w
+umask
+umask a-w
+umask
+umask o=u
+umask
+umask 022
+
+ulimit -n 256
+ulimit -n
+ulimit -Sn 128
+ulimit -Sn
+ulimit -Hn
+ulimit -c 0
+ulimit -c
+ulimit -t
+times > /dev/null && echo "times ok"
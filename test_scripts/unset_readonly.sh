@@ -0,0 +1,34 @@
+#!/bin/sh
+
+EMPTY=
+echo "[${EMPTY:-default}]"
+echo "[${EMPTY}]"
+unset EMPTY
+echo "[${EMPTY:-unset now}]"
+
+greet() {
+    echo "hello from greet"
+}
+greet
+unset -f greet
+
+both() {
+    echo "still here"
+}
+both=value
+unset -v both
+both
+echo "[$both]"
+
+# without -f only the variable goes
+kept() {
+    echo "kept is still a function"
+}
+kept=value
+unset kept
+kept
+echo "[$kept]"
+
+readonly CONST=fixed
+echo $CONST
+readonly -p
@@ -14,3 +14,9 @@ echo "$SOME$SOME" "$FOO" hello "  $VAR"
 echo "Hello, ${USER}"
 echo "I am, `whoami`; or you may call me $(whoami)"
 echo "Hello $SOME$SOME"
+PREFIXED=1 true
+echo "[${PREFIXED-unset}]"
+PREFIXED=2 env | grep '^PREFIXED='
+show() { echo "show sees $PREFIXED"; }
+PREFIXED=3 show
+echo "[${PREFIXED-unset}]"
@@ -0,0 +1,30 @@
+empty=
+set_v=value
+unset gone
+echo "1 ${empty-unset} ${empty:-null}"
+echo "2 ${gone-unset} ${gone:-null}"
+echo "3 ${set_v-x} ${set_v:-x}"
+echo "4 [${empty+alt}] [${empty:+alt}] [${gone+alt}] [${set_v:+alt}]"
+echo "5 ${a=first} ${a=second} $a"
+b=
+echo "6 [${b=kept}] [${b:=replaced}] $b"
+echo "7 ${set_v?oops} ${set_v:?oops}"
+echo "8 ${gone:-two words here}"
+echo 9 ${gone:-$set_v and "$set_v"}
+echo "10 ${gone:-${set_v:+nested}}"
+echo 11 ${gone-'single quoted'}
+echo "12 ${empty?fine}"
+{ x=$(echo ${gone?custom message}); } 2>/dev/null
+echo "13 [$x]"
+{ y=$(echo ${empty:?}); } 2>/dev/null
+echo "14 [$y]"
+echo "15 ${1-nopos} ${1:+pos}"
+unset q1 q2
+echo "16 [${gone-"a b"}] [${empty:-"c  d"}] [${set_v-"no"}]"
+echo "17 [${q1="e f"}] [${empty:="g h"}] [$q1] [$empty]"
+echo "18 [${set_v?"not shown"}] [${set_v:?"not shown"}]"
+echo "19 [${set_v+"i j"}] [${set_v:+"k $set_v"}] [${gone+"no"}]"
+{ z=$(echo "${gone?"quoted message"}"); } 2>/dev/null
+echo "20 [$z]"
+m=$({ echo "${gone:?"quoted message"}"; } 2>&1)
+echo "21 ${m##*: }"
@@ -0,0 +1,14 @@
+{
+set -x
+echo "a b" "" x=1
+y=2
+PS4="> "
+echo $y
+f() { echo in; }
+f arg
+v=$(echo sub)
+echo "$v" | cat
+set +x
+} 2>&1
+echo "cost: $ 5 # not a comment"
+echo "$"